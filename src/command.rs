@@ -1,28 +1,57 @@
+mod halt;
+mod interrupt;
+mod job_slot;
+mod metrics;
 mod path_cache;
+mod result;
+mod retry;
 
 use anyhow::Context;
 
-use tokio::sync::Semaphore;
+use tokio::{sync::Semaphore, time::Instant};
 
 use tracing::{debug, info, instrument, span_enabled, warn, Level, Span};
 
-use std::sync::Arc;
+use std::{
+    ffi::OsString,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
 
 use crate::{
-    command_line_args::CommandLineArgs,
-    common::OwnedCommandAndArgs,
+    command_line_args::ChildSignal,
+    command_line_args::{CommandLineArgs, OutputFormat},
+    common::{JobOptions, OwnedCommandAndArgs},
     input::{InputLineNumber, InputMessage, InputProducer},
-    output::{OutputSender, OutputWriter},
-    process::ChildProcessFactory,
+    joblog::{JobLog, ResumeFilter},
+    output::{JobRecord, OutputBuffer, OutputSender, OutputWriter},
+    parser,
+    process::{ChildProcessExecutionError, ChildProcessFactory, ChildProcessOutput},
     progress::Progress,
+    results::{JobResult, JobResults},
+};
+
+use self::{
+    halt::HaltState,
+    interrupt::InterruptListener,
+    job_slot::{JobSlot, JobSlots},
+    path_cache::CommandPathCache,
+    retry::RetryPolicy,
 };
 
-use self::path_cache::CommandPathCache;
+pub use self::{
+    metrics::CommandMetrics,
+    result::{CommandOutcome, CommandResult},
+};
 
 #[derive(Debug)]
 struct Command {
     command_and_args: OwnedCommandAndArgs,
     input_line_number: InputLineNumber,
+    sequence_number: usize,
+    input_values: Vec<OsString>,
+    job_options: JobOptions,
+    stdin: Option<Arc<[u8]>>,
 }
 
 impl Command {
@@ -36,15 +65,82 @@ impl Command {
             child_pid,
         ),
         level = "debug")]
-    async fn run(self, child_process_factory: ChildProcessFactory, output_sender: OutputSender) {
+    async fn run(self, run_context: &CommandRunContext, output_sender: OutputSender) {
         debug!("begin run");
 
-        let OwnedCommandAndArgs { command_path, args } = &self.command_and_args;
+        run_context.command_metrics.increment_commands_run();
+
+        let mut attempt = 1;
+
+        let (command_result, child_process_output) = loop {
+            let start_time = SystemTime::now();
+            let start_instant = Instant::now();
+
+            let (outcome, child_process_output) =
+                self.run_child_process(run_context, &output_sender).await;
+
+            if run_context.retry_policy.should_retry(attempt, &outcome)
+                && run_context.wait_for_retry(&self, attempt).await
+            {
+                attempt += 1;
+                continue;
+            }
+
+            let command_result = CommandResult {
+                start_time,
+                runtime: start_instant.elapsed(),
+                outcome,
+                stdout_bytes: child_process_output
+                    .as_ref()
+                    .map_or(0, |output| output.stdout_bytes),
+                stderr_bytes: child_process_output
+                    .as_ref()
+                    .map_or(0, |output| output.stderr_bytes),
+            };
+
+            break (command_result, child_process_output);
+        };
+
+        run_context
+            .command_completed(&self, &command_result, child_process_output.as_ref())
+            .await;
+
+        let (stdout, stderr) = child_process_output
+            .map(|output| (output.stdout, output.stderr))
+            .unwrap_or_default();
+
+        let job_record = output_sender.json().then(|| {
+            JobRecord::new(
+                self.sequence_number,
+                &self.input_line_number,
+                &self.command_and_args,
+                &command_result,
+            )
+        });
 
-        let child_process = match child_process_factory.spawn(command_path, args).await {
+        output_sender.send(stdout, stderr, job_record).await;
+
+        debug!("end run");
+    }
+
+    async fn run_child_process(
+        &self,
+        run_context: &CommandRunContext,
+        output_sender: &OutputSender,
+    ) -> (CommandOutcome, Option<ChildProcessOutput>) {
+        let OwnedCommandAndArgs {
+            command_path, args, ..
+        } = &self.command_and_args;
+
+        let child_process = match run_context
+            .child_process_factory
+            .clone()
+            .spawn(command_path, args, &self.job_options, self.stdin.clone())
+            .await
+        {
             Err(e) => {
                 warn!("spawn error command: {}: {}", self, e);
-                return;
+                return (CommandOutcome::SpawnError, None);
             }
             Ok(child_process) => child_process,
         };
@@ -56,17 +152,39 @@ impl Command {
             debug!("spawned child process, awaiting completion");
         }
 
-        match child_process.await_completion().await {
+        let result = tokio::select! {
+            result = child_process.await_completion(output_sender) => result,
+            _ = run_context.halt_state.wait_for_kill() => {
+                warn!("killed command due to halt: {}", self);
+                return (CommandOutcome::Killed, None);
+            }
+        };
+
+        match result {
             Err(e) => {
                 warn!("child process error command: {} error: {}", self, e);
+                match e {
+                    ChildProcessExecutionError::Timeout(_, exit_status, child_process_output) => (
+                        CommandOutcome::Timeout(exit_status),
+                        child_process_output.map(|output| *output),
+                    ),
+                    ChildProcessExecutionError::IOError(_) => (CommandOutcome::IOError, None),
+                }
             }
-            Ok(output) => {
-                debug!("command exit status = {}", output.status);
-                output_sender.send(output).await;
+            Ok((exit_status, child_process_output)) => {
+                debug!("command exit status = {}", exit_status);
+                (
+                    CommandOutcome::Exited(exit_status),
+                    Some(child_process_output),
+                )
             }
-        };
+        }
+    }
+}
 
-        debug!("end run");
+impl Command {
+    fn replace_job_slot(&mut self, job_slot: usize) {
+        self.command_and_args.replace_job_slots(job_slot);
     }
 }
 
@@ -80,57 +198,185 @@ impl std::fmt::Display for Command {
     }
 }
 
-pub struct CommandService {
+/// State shared by all running commands.
+struct CommandRunContext {
     child_process_factory: ChildProcessFactory,
+    command_metrics: Arc<CommandMetrics>,
+    halt_state: HaltState,
+    job_log: Option<JobLog>,
+    job_results: Option<JobResults>,
+    retry_policy: RetryPolicy,
+}
+
+impl CommandRunContext {
+    /// Waits out the backoff delay before the next attempt of `command`.
+    /// Returns false if the command should not be retried because of a halt.
+    async fn wait_for_retry(&self, command: &Command, attempt: usize) -> bool {
+        if self.halt_state.halted() {
+            return false;
+        }
+
+        let delay = self.retry_policy.delay(attempt);
+
+        warn!(
+            "retrying command after {:?} attempt={}: {}",
+            delay,
+            attempt + 1,
+            command
+        );
+
+        tokio::select! {
+            _ = tokio::time::sleep(delay) => {},
+            _ = self.halt_state.wait_for_kill() => return false,
+        }
+
+        if self.halt_state.halted() {
+            return false;
+        }
+
+        self.command_metrics.increment_retries();
+
+        true
+    }
+
+    async fn command_completed(
+        &self,
+        command: &Command,
+        command_result: &CommandResult,
+        output: Option<&ChildProcessOutput>,
+    ) {
+        self.command_metrics.record_outcome(&command_result.outcome);
+
+        if let Some(job_log) = &self.job_log {
+            job_log
+                .write(
+                    command.sequence_number,
+                    &command.input_line_number,
+                    &command.command_and_args,
+                    command_result,
+                )
+                .await;
+        }
+
+        if let Some(job_results) = &self.job_results {
+            job_results
+                .write(JobResult {
+                    sequence_number: command.sequence_number,
+                    input_line_number: &command.input_line_number,
+                    input_values: &command.input_values,
+                    command_and_args: &command.command_and_args,
+                    command_result,
+                    output,
+                })
+                .await;
+        }
+
+        self.halt_state.command_completed(&self.command_metrics);
+    }
+}
+
+pub struct RunSummary {
+    pub command_metrics: Arc<CommandMetrics>,
+    /// Set if the run was interrupted by a signal.
+    pub interrupt_signal: Option<ChildSignal>,
+}
+
+pub struct CommandService {
     command_line_args: &'static CommandLineArgs,
     command_path_cache: CommandPathCache,
     command_semaphore: Arc<Semaphore>,
+    job_slots: Option<Arc<JobSlots>>,
     output_writer: OutputWriter,
     progress: Arc<Progress>,
+    resume_filter: Option<ResumeFilter>,
+    run_context: Arc<CommandRunContext>,
 }
 
 impl CommandService {
-    pub fn new(command_line_args: &'static CommandLineArgs, progress: Arc<Progress>) -> Self {
-        Self {
-            child_process_factory: ChildProcessFactory::new(command_line_args),
+    pub async fn new(
+        command_line_args: &'static CommandLineArgs,
+        progress: Arc<Progress>,
+    ) -> anyhow::Result<Self> {
+        // Read the joblog before JobLog::new opens it for writing.
+        let resume_filter = ResumeFilter::new(command_line_args).await?;
+
+        let halt_state = HaltState::new(command_line_args);
+
+        let run_context = CommandRunContext {
+            child_process_factory: ChildProcessFactory::new(
+                command_line_args,
+                halt_state.forwarded_signal_receiver(),
+            ),
+            command_metrics: Arc::new(CommandMetrics::default()),
+            halt_state,
+            job_log: JobLog::new(command_line_args).await?,
+            job_results: JobResults::new(command_line_args).await?,
+            retry_policy: RetryPolicy::new(command_line_args),
+        };
+
+        let run_context = Arc::new(run_context);
+
+        let interrupt_listener = InterruptListener::new()?;
+
+        let run_context_clone = Arc::clone(&run_context);
+
+        tokio::spawn(async move { interrupt_listener.run(&run_context_clone.halt_state).await });
+
+        Ok(Self {
             command_line_args,
             command_path_cache: CommandPathCache::new(command_line_args),
             command_semaphore: Arc::new(Semaphore::new(command_line_args.jobs)),
+            job_slots: parser::uses_job_slot(command_line_args)
+                .then(|| JobSlots::new(command_line_args.jobs)),
             output_writer: OutputWriter::new(command_line_args),
             progress,
-        }
+            resume_filter,
+            run_context,
+        })
     }
 
-    async fn spawn_command(
-        &self,
-        command_and_args: OwnedCommandAndArgs,
-        input_line_number: InputLineNumber,
-    ) -> anyhow::Result<()> {
-        let command = Command {
-            command_and_args,
-            input_line_number,
-        };
-
+    async fn spawn_command(&self, mut command: Command) -> anyhow::Result<()> {
         if self.command_line_args.dry_run {
             info!("{}", command);
 
             return Ok(());
         }
 
-        let child_process_factory = self.child_process_factory.clone();
-
-        let output_sender = self.output_writer.sender();
-
         let progress_clone = Arc::clone(&self.progress);
 
+        let run_context_clone = Arc::clone(&self.run_context);
+
         let permit = Arc::clone(&self.command_semaphore)
             .acquire_owned()
             .await
             .context("command_semaphore.acquire_owned error")?;
 
+        // Another command may have triggered a halt while waiting for the permit.
+        if self.run_context.halt_state.halted() {
+            return Ok(());
+        }
+
+        let job_slot = self.job_slots.as_ref().map(JobSlots::acquire);
+
+        if let Some(job_slot) = &job_slot {
+            command.replace_job_slot(job_slot.number());
+        }
+
+        // Created once the job slot is known, which the tag may contain.
+        let output_sender = self
+            .output_writer
+            .sender(
+                &command.input_values,
+                command.sequence_number,
+                &command.input_line_number,
+                job_slot.as_ref().map(JobSlot::number),
+            )
+            .await?;
+
         tokio::spawn(async move {
-            command.run(child_process_factory, output_sender).await;
+            command.run(&run_context_clone, output_sender).await;
 
+            drop(job_slot);
             drop(permit);
 
             progress_clone.command_finished();
@@ -143,48 +389,158 @@ impl CommandService {
         let InputMessage {
             command_and_args,
             input_line_number,
+            sequence_number,
+            input_values,
+            job_options,
+            stdin,
         } = input_message;
 
+        // Kept to record a command that can't be resolved with --output-format=json.
+        let unresolved_command_and_args = (self.command_line_args.output_format
+            == OutputFormat::Json
+            && !self.command_line_args.dry_run)
+            .then(|| command_and_args.clone());
+
         let Some(command_and_args) = self
             .command_path_cache
             .resolve_command_path(command_and_args)
             .await?
         else {
+            let command_result = CommandResult {
+                start_time: SystemTime::now(),
+                runtime: Duration::ZERO,
+                outcome: CommandOutcome::SpawnError,
+                stdout_bytes: 0,
+                stderr_bytes: 0,
+            };
+
+            let run_context = &self.run_context;
+            run_context.command_metrics.increment_commands_run();
+            run_context
+                .command_metrics
+                .record_outcome(&command_result.outcome);
+            run_context
+                .halt_state
+                .command_completed(&run_context.command_metrics);
+
+            if let Some(command_and_args) = unresolved_command_and_args {
+                let job_record = JobRecord::new(
+                    sequence_number,
+                    &input_line_number,
+                    &command_and_args,
+                    &command_result,
+                );
+
+                self.output_writer
+                    .sender(&input_values, sequence_number, &input_line_number, None)
+                    .await?
+                    .send(
+                        OutputBuffer::default(),
+                        OutputBuffer::default(),
+                        Some(job_record),
+                    )
+                    .await;
+            }
+
             return Ok(());
         };
 
-        self.spawn_command(command_and_args, input_line_number)
-            .await?;
+        self.spawn_command(Command {
+            command_and_args,
+            input_line_number,
+            sequence_number,
+            input_values,
+            job_options,
+            stdin,
+        })
+        .await?;
 
         Ok(())
     }
 
     async fn process_inputs(&self) -> anyhow::Result<()> {
-        let mut input_producer = InputProducer::new(self.command_line_args, &self.progress)?;
+        let mut input_producer = InputProducer::new(
+            self.command_line_args,
+            &self.progress,
+            &self.run_context.command_metrics,
+        )
+        .await?;
+
+        loop {
+            let input_message = tokio::select! {
+                input_message = input_producer.receiver().recv() => input_message,
+                _ = self.run_context.halt_state.wait_for_halt() => None,
+            };
+
+            let Some(input_message) = input_message else {
+                break;
+            };
+
+            if self.run_context.halt_state.halted() {
+                break;
+            }
+
+            if self
+                .resume_filter
+                .as_ref()
+                .is_some_and(|resume_filter| resume_filter.skip(input_message.sequence_number))
+            {
+                debug!(
+                    "resume skipping sequence_number {} line {}",
+                    input_message.sequence_number, input_message.input_line_number
+                );
+                self.progress.command_finished();
+                continue;
+            }
 
-        while let Some(input_message) = input_producer.receiver().recv().await {
             self.process_input_message(input_message).await?;
         }
 
+        if self.run_context.halt_state.halted() {
+            debug!("halted, stopping input producer");
+            input_producer.stop();
+        }
+
         input_producer.wait_for_completion().await?;
 
         Ok(())
     }
 
     #[instrument(name = "CommandService::run_commands", skip_all, level = "debug")]
-    pub async fn run_commands(self) -> anyhow::Result<()> {
+    pub async fn run_commands(self) -> anyhow::Result<RunSummary> {
         debug!("begin run_commands");
 
-        self.process_inputs().await?;
+        let process_inputs_result = self.process_inputs().await;
+
+        // Running commands still finish and have their output written before the
+        // error is returned.
+        if process_inputs_result.is_err() {
+            self.run_context.halt_state.halt_on_input_error();
+        }
 
         debug!("before output_writer.wait_for_completion",);
 
         self.output_writer.wait_for_completion().await?;
 
+        if let Some(job_log) = &self.run_context.job_log {
+            job_log.flush().await?;
+        }
+
+        if let Some(job_results) = &self.run_context.job_results {
+            job_results.flush().await?;
+        }
+
         self.progress.finish();
 
-        debug!("end run_commands");
+        process_inputs_result?;
 
-        Ok(())
+        let command_metrics = Arc::clone(&self.run_context.command_metrics);
+
+        debug!("end run_commands command_metrics = {}", command_metrics);
+
+        Ok(RunSummary {
+            command_metrics,
+            interrupt_signal: self.run_context.halt_state.interrupt_signal(),
+        })
     }
 }
@@ -0,0 +1,158 @@
+use tokio::sync::watch;
+
+use tracing::warn;
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::command_line_args::{
+    ChildSignal, CommandLineArgs, HaltCondition, HaltPolicy, HaltThreshold,
+};
+
+use super::metrics::CommandMetrics;
+
+/// Like GNU parallel, percentage thresholds only apply once this many commands completed.
+const MIN_COMMANDS_FOR_PERCENT: u64 = 3;
+
+pub struct HaltState {
+    policy: HaltPolicy,
+    halted: AtomicBool,
+    halt_sender: watch::Sender<bool>,
+    kill_sender: watch::Sender<bool>,
+    forwarded_signal_sender: watch::Sender<Option<ChildSignal>>,
+}
+
+impl HaltState {
+    pub fn new(command_line_args: &CommandLineArgs) -> Self {
+        let (halt_sender, _) = watch::channel(false);
+        let (kill_sender, _) = watch::channel(false);
+        let (forwarded_signal_sender, _) = watch::channel(None);
+
+        Self {
+            policy: command_line_args.halt,
+            halted: AtomicBool::new(false),
+            halt_sender,
+            kill_sender,
+            forwarded_signal_sender,
+        }
+    }
+
+    pub fn halted(&self) -> bool {
+        self.halted.load(Ordering::SeqCst)
+    }
+
+    /// Stops starting new commands.  Returns false if already halted.
+    fn halt(&self) -> bool {
+        if self.halted.swap(true, Ordering::SeqCst) {
+            return false;
+        }
+
+        self.halt_sender.send_replace(true);
+
+        true
+    }
+
+    /// Stops starting new commands after an error reading inputs, letting running
+    /// commands finish.
+    pub fn halt_on_input_error(&self) {
+        if self.halt() {
+            warn!("halting after input error, waiting for running commands");
+        }
+    }
+
+    /// Resolves once no new commands should be started.
+    pub async fn wait_for_halt(&self) {
+        let mut receiver = self.halt_sender.subscribe();
+
+        // Error means the sender was dropped, in which case no halt will ever happen.
+        if receiver.wait_for(|halted| *halted).await.is_err() {
+            std::future::pending::<()>().await;
+        }
+    }
+
+    /// Resolves when running commands should be killed.  Never resolves unless
+    /// the halt policy is "now" and its condition has been met, or a second
+    /// interrupt signal was received.
+    pub async fn wait_for_kill(&self) {
+        let mut receiver = self.kill_sender.subscribe();
+
+        // Error means the sender was dropped, in which case no kill will ever happen.
+        if receiver.wait_for(|kill| *kill).await.is_err() {
+            std::future::pending::<()>().await;
+        }
+    }
+
+    /// Called after each command completes to check the halt condition.
+    pub fn command_completed(&self, command_metrics: &CommandMetrics) {
+        let (condition, kill) = match self.policy {
+            HaltPolicy::Never => return,
+            HaltPolicy::Soon(condition) => (condition, false),
+            HaltPolicy::Now(condition) => (condition, true),
+        };
+
+        if !Self::condition_met(condition, command_metrics) {
+            return;
+        }
+
+        if self.halt() {
+            warn!(
+                "halting due to halt policy {:?}: {}",
+                self.policy, command_metrics
+            );
+        }
+
+        if kill {
+            self.kill_sender.send_replace(true);
+        }
+    }
+
+    /// Receives signals to forward to the process groups of running commands.
+    pub fn forwarded_signal_receiver(&self) -> watch::Receiver<Option<ChildSignal>> {
+        self.forwarded_signal_sender.subscribe()
+    }
+
+    /// Signal that interrupted this run, if any.
+    pub fn interrupt_signal(&self) -> Option<ChildSignal> {
+        *self.forwarded_signal_sender.borrow()
+    }
+
+    /// Called when rust-parallel receives an interrupt signal.  The first signal stops
+    /// starting new commands and is forwarded to running commands, any later signal
+    /// kills running commands.
+    pub fn interrupt(&self, signal: ChildSignal) {
+        if self.interrupt_signal().is_none() {
+            warn!(
+                "received {:?} signal, forwarding to running commands, signal again to kill them",
+                signal
+            );
+            self.halt();
+            self.forwarded_signal_sender.send_replace(Some(signal));
+        } else {
+            warn!("received {:?} signal, killing running commands", signal);
+            self.kill_sender.send_replace(true);
+        }
+    }
+
+    fn condition_met(condition: HaltCondition, command_metrics: &CommandMetrics) -> bool {
+        let (threshold, value) = match condition {
+            HaltCondition::Fail(threshold) => (threshold, command_metrics.total_failures()),
+            HaltCondition::Success(threshold) => (threshold, command_metrics.successes()),
+        };
+
+        match threshold {
+            HaltThreshold::Count(count) => value >= count,
+            HaltThreshold::Percent(percent) => {
+                let commands_completed = command_metrics.commands_completed();
+
+                if commands_completed < MIN_COMMANDS_FOR_PERCENT {
+                    return false;
+                }
+
+                let total_commands = command_metrics
+                    .total_commands()
+                    .unwrap_or(commands_completed);
+
+                (value as f64 * 100f64 / total_commands as f64) >= percent
+            }
+        }
+    }
+}
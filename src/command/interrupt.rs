@@ -0,0 +1,57 @@
+use anyhow::Context;
+
+#[cfg(unix)]
+use tokio::signal::unix::{signal, Signal, SignalKind};
+
+use crate::command_line_args::ChildSignal;
+
+use super::halt::HaltState;
+
+/// Replaces the default handling of SIGINT and SIGTERM (Ctrl-C on Windows),
+/// which would end rust-parallel without flushing output.
+pub struct InterruptListener {
+    #[cfg(unix)]
+    interrupt: Signal,
+    #[cfg(unix)]
+    terminate: Signal,
+}
+
+impl InterruptListener {
+    #[cfg(unix)]
+    pub fn new() -> anyhow::Result<Self> {
+        Ok(Self {
+            interrupt: signal(SignalKind::interrupt()).context("SIGINT handler error")?,
+            terminate: signal(SignalKind::terminate()).context("SIGTERM handler error")?,
+        })
+    }
+
+    #[cfg(not(unix))]
+    pub fn new() -> anyhow::Result<Self> {
+        Ok(Self {})
+    }
+
+    #[cfg(unix)]
+    async fn recv(&mut self) -> ChildSignal {
+        tokio::select! {
+            _ = self.interrupt.recv() => ChildSignal::Int,
+            _ = self.terminate.recv() => ChildSignal::Term,
+        }
+    }
+
+    #[cfg(not(unix))]
+    async fn recv(&mut self) -> ChildSignal {
+        if tokio::signal::ctrl_c().await.is_err() {
+            std::future::pending::<()>().await;
+        }
+
+        ChildSignal::Int
+    }
+
+    pub async fn run(mut self, halt_state: &HaltState) {
+        loop {
+            let signal = self.recv().await;
+
+            halt_state.interrupt(signal);
+        }
+    }
+}
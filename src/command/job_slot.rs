@@ -0,0 +1,75 @@
+use std::{
+    collections::BTreeSet,
+    sync::{Arc, Mutex},
+};
+
+/// Job slot numbers 1..=jobs for the {%} replacement string.  The lowest free slot
+/// is handed out first, as in GNU parallel.
+pub struct JobSlots {
+    free_slots: Mutex<BTreeSet<usize>>,
+}
+
+impl JobSlots {
+    pub fn new(jobs: usize) -> Arc<Self> {
+        Arc::new(Self {
+            free_slots: Mutex::new((1..=jobs).collect()),
+        })
+    }
+
+    /// Must be called while holding a command semaphore permit, which guarantees a free slot.
+    pub fn acquire(self: &Arc<Self>) -> JobSlot {
+        let number = self
+            .free_slots
+            .lock()
+            .unwrap()
+            .pop_first()
+            .expect("no free job slot while holding a command semaphore permit");
+
+        JobSlot {
+            job_slots: Arc::clone(self),
+            number,
+        }
+    }
+}
+
+/// Returns the slot to `JobSlots` when dropped.
+pub struct JobSlot {
+    job_slots: Arc<JobSlots>,
+    number: usize,
+}
+
+impl JobSlot {
+    pub fn number(&self) -> usize {
+        self.number
+    }
+}
+
+impl Drop for JobSlot {
+    fn drop(&mut self) {
+        self.job_slots
+            .free_slots
+            .lock()
+            .unwrap()
+            .insert(self.number);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_lowest_free_slot() {
+        let job_slots = JobSlots::new(3);
+
+        let slot1 = job_slots.acquire();
+        let slot2 = job_slots.acquire();
+        let slot3 = job_slots.acquire();
+
+        assert_eq!([slot1.number(), slot2.number(), slot3.number()], [1, 2, 3]);
+
+        drop(slot2);
+
+        assert_eq!(job_slots.acquire().number(), 2);
+    }
+}
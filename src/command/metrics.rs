@@ -0,0 +1,161 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use super::result::CommandOutcome;
+
+/// GNU parallel caps the exit status at 101 to mean "more than 100 commands failed".
+const MAX_FAILURES_EXIT_CODE: u64 = 101;
+
+#[derive(Debug, Default)]
+pub struct CommandMetrics {
+    /// Commands of the whole run once all inputs have been parsed, 0 until then.
+    total_commands: AtomicU64,
+    commands_run: AtomicU64,
+    retries: AtomicU64,
+    successes: AtomicU64,
+    exit_status_errors: AtomicU64,
+    spawn_errors: AtomicU64,
+    timeouts: AtomicU64,
+    io_errors: AtomicU64,
+    /// Inputs skipped because they can't be parsed, which count as failures of the run.
+    invalid_inputs: AtomicU64,
+}
+
+impl CommandMetrics {
+    pub fn set_total_commands(&self, total_commands: usize) {
+        self.total_commands.store(
+            total_commands.try_into().unwrap_or(u64::MAX),
+            Ordering::SeqCst,
+        );
+    }
+
+    /// Number of commands of the whole run, if known yet.
+    pub fn total_commands(&self) -> Option<u64> {
+        Some(self.total_commands.load(Ordering::SeqCst)).filter(|total| *total > 0)
+    }
+
+    pub fn increment_commands_run(&self) {
+        self.commands_run.fetch_add(1, Ordering::SeqCst);
+    }
+
+    pub fn increment_retries(&self) {
+        self.retries.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn increment_successes(&self) {
+        self.successes.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn increment_exit_status_errors(&self) {
+        self.exit_status_errors.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn increment_spawn_errors(&self) {
+        self.spawn_errors.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn increment_timeouts(&self) {
+        self.timeouts.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn increment_io_errors(&self) {
+        self.io_errors.fetch_add(1, Ordering::SeqCst);
+    }
+
+    pub fn increment_invalid_inputs(&self) {
+        self.invalid_inputs.fetch_add(1, Ordering::SeqCst);
+    }
+
+    pub fn record_outcome(&self, outcome: &CommandOutcome) {
+        match outcome {
+            CommandOutcome::Exited(exit_status) if exit_status.success() => {
+                self.increment_successes()
+            }
+            CommandOutcome::Exited(_) => self.increment_exit_status_errors(),
+            CommandOutcome::SpawnError => self.increment_spawn_errors(),
+            CommandOutcome::Timeout(_) => self.increment_timeouts(),
+            CommandOutcome::IOError => self.increment_io_errors(),
+            CommandOutcome::Killed => {}
+        }
+    }
+
+    pub fn successes(&self) -> u64 {
+        self.successes.load(Ordering::SeqCst)
+    }
+
+    /// Commands that succeeded or failed, not counting killed commands.
+    pub fn commands_completed(&self) -> u64 {
+        self.successes() + self.total_failures()
+    }
+
+    pub fn total_failures(&self) -> u64 {
+        self.exit_status_errors.load(Ordering::SeqCst)
+            + self.spawn_errors.load(Ordering::SeqCst)
+            + self.timeouts.load(Ordering::SeqCst)
+            + self.io_errors.load(Ordering::SeqCst)
+    }
+
+    /// Failed commands and invalid inputs.
+    fn run_failures(&self) -> u64 {
+        self.total_failures() + self.invalid_inputs.load(Ordering::SeqCst)
+    }
+
+    pub fn error_occurred(&self) -> bool {
+        self.run_failures() > 0
+    }
+
+    /// Exit code for the process: 0 if every command succeeded and every input could be
+    /// parsed, otherwise the number of failed commands and invalid inputs capped at 101.
+    pub fn exit_code(&self) -> i32 {
+        self.run_failures()
+            .min(MAX_FAILURES_EXIT_CODE)
+            .try_into()
+            .unwrap_or(1)
+    }
+}
+
+impl std::fmt::Display for CommandMetrics {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "commands_run={},retries={},successes={},exit_status_errors={},spawn_errors={},timeouts={},io_errors={},invalid_inputs={}",
+            self.commands_run.load(Ordering::SeqCst),
+            self.retries.load(Ordering::SeqCst),
+            self.successes.load(Ordering::SeqCst),
+            self.exit_status_errors.load(Ordering::SeqCst),
+            self.spawn_errors.load(Ordering::SeqCst),
+            self.timeouts.load(Ordering::SeqCst),
+            self.io_errors.load(Ordering::SeqCst),
+            self.invalid_inputs.load(Ordering::SeqCst),
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_exit_code() {
+        let command_metrics = CommandMetrics::default();
+
+        assert!(!command_metrics.error_occurred());
+        assert_eq!(command_metrics.exit_code(), 0);
+
+        command_metrics.increment_exit_status_errors();
+        command_metrics.increment_timeouts();
+
+        assert!(command_metrics.error_occurred());
+        assert_eq!(command_metrics.exit_code(), 2);
+
+        command_metrics.increment_invalid_inputs();
+
+        assert_eq!(command_metrics.exit_code(), 3);
+        assert_eq!(command_metrics.total_failures(), 2);
+
+        for _ in 0..200 {
+            command_metrics.increment_spawn_errors();
+        }
+
+        assert_eq!(command_metrics.exit_code(), 101);
+    }
+}
@@ -0,0 +1,67 @@
+use std::{
+    process::ExitStatus,
+    time::{Duration, SystemTime},
+};
+
+#[derive(Clone, Copy, Debug)]
+pub enum CommandOutcome {
+    /// Child process exited normally or was terminated by a signal.
+    Exited(ExitStatus),
+
+    /// Command could not be resolved or spawned.
+    SpawnError,
+
+    /// Child process exceeded the command timeout.  Contains the exit status
+    /// of the child process after the timeout signals were sent, if known.
+    Timeout(Option<ExitStatus>),
+
+    /// I/O error while waiting for the child process.
+    IOError,
+
+    /// Child process was killed because of the halt policy.
+    Killed,
+}
+
+impl CommandOutcome {
+    pub fn exit_code(&self) -> Option<i32> {
+        match self {
+            Self::Exited(exit_status) => exit_status.code(),
+            _ => None,
+        }
+    }
+
+    #[cfg(unix)]
+    pub fn signal(&self) -> Option<i32> {
+        use std::os::unix::process::ExitStatusExt;
+
+        match self {
+            Self::Exited(exit_status) | Self::Timeout(Some(exit_status)) => exit_status.signal(),
+            _ => None,
+        }
+    }
+
+    #[cfg(not(unix))]
+    pub fn signal(&self) -> Option<i32> {
+        None
+    }
+
+    pub fn status_name(&self) -> &'static str {
+        match self {
+            Self::Exited(exit_status) if exit_status.success() => "success",
+            Self::Exited(_) => "exit_status_error",
+            Self::SpawnError => "spawn_error",
+            Self::Timeout(_) => "timeout",
+            Self::IOError => "io_error",
+            Self::Killed => "killed",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct CommandResult {
+    pub start_time: SystemTime,
+    pub runtime: Duration,
+    pub outcome: CommandOutcome,
+    pub stdout_bytes: usize,
+    pub stderr_bytes: usize,
+}
@@ -0,0 +1,91 @@
+use rand::Rng;
+
+use tokio::time::Duration;
+
+use crate::command_line_args::CommandLineArgs;
+
+use super::result::CommandOutcome;
+
+/// Backoff doubles for each retry up to this many doublings.
+const MAX_BACKOFF_DOUBLINGS: u32 = 16;
+
+/// Random jitter of up to this fraction of the backoff is added to each delay.
+const MAX_JITTER_FRACTION: f64 = 0.5;
+
+pub struct RetryPolicy {
+    retries: usize,
+    retry_delay: Duration,
+}
+
+impl RetryPolicy {
+    pub fn new(command_line_args: &CommandLineArgs) -> Self {
+        Self {
+            retries: command_line_args.retries,
+            retry_delay: Duration::from_secs_f64(command_line_args.retry_delay),
+        }
+    }
+
+    /// `attempt` is the 1-based number of the attempt that just finished.
+    pub fn should_retry(&self, attempt: usize, outcome: &CommandOutcome) -> bool {
+        let failed = match outcome {
+            CommandOutcome::Exited(exit_status) => !exit_status.success(),
+            CommandOutcome::SpawnError | CommandOutcome::Timeout(_) | CommandOutcome::IOError => {
+                true
+            }
+            CommandOutcome::Killed => false,
+        };
+
+        failed && attempt <= self.retries
+    }
+
+    /// Delay before the next attempt: retry_delay * 2^(attempt - 1) plus random jitter.
+    pub fn delay(&self, attempt: usize) -> Duration {
+        let doublings = u32::try_from(attempt.saturating_sub(1))
+            .unwrap_or(MAX_BACKOFF_DOUBLINGS)
+            .min(MAX_BACKOFF_DOUBLINGS);
+
+        let backoff = self.retry_delay * 2u32.pow(doublings);
+
+        let jitter = backoff.mul_f64(rand::thread_rng().gen_range(0f64..=MAX_JITTER_FRACTION));
+
+        backoff + jitter
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_should_retry() {
+        let command_line_args = CommandLineArgs {
+            retries: 2,
+            ..Default::default()
+        };
+
+        let retry_policy = RetryPolicy::new(&command_line_args);
+
+        assert!(retry_policy.should_retry(1, &CommandOutcome::Timeout(None)));
+        assert!(retry_policy.should_retry(2, &CommandOutcome::SpawnError));
+        assert!(!retry_policy.should_retry(3, &CommandOutcome::Timeout(None)));
+        assert!(!retry_policy.should_retry(1, &CommandOutcome::Killed));
+    }
+
+    #[test]
+    fn test_delay() {
+        let command_line_args = CommandLineArgs {
+            retries: 5,
+            retry_delay: 1.0,
+            ..Default::default()
+        };
+
+        let retry_policy = RetryPolicy::new(&command_line_args);
+
+        for (attempt, backoff_seconds) in [(1, 1.0), (2, 2.0), (3, 4.0), (100, 65536.0)] {
+            let delay = retry_policy.delay(attempt).as_secs_f64();
+
+            assert!(delay >= backoff_seconds);
+            assert!(delay <= backoff_seconds * (1.0 + MAX_JITTER_FRACTION));
+        }
+    }
+}
@@ -4,8 +4,16 @@ use tokio::sync::OnceCell;
 
 use tracing::debug;
 
+use std::ffi::{OsStr, OsString};
+
 pub const COMMANDS_FROM_ARGS_SEPARATOR: &str = ":::";
 
+pub const LINKED_COMMANDS_FROM_ARGS_SEPARATOR: &str = ":::+";
+
+pub const ARGUMENT_FILES_SEPARATOR: &str = "::::";
+
+pub const LINKED_ARGUMENT_FILES_SEPARATOR: &str = "::::+";
+
 /// Execute commands in parallel
 ///
 /// By Aaron Riekenberg <aaron.riekenberg@gmail.com>
@@ -20,6 +28,9 @@ pub struct CommandLineArgs {
     pub discard_output: Option<DiscardOutput>,
 
     /// Input file or - for stdin.  Defaults to stdin if no inputs are specified.
+    ///
+    /// Multiple input files are read one after another, or side by side one line
+    /// from each file per command if the command uses positional replacement strings.
     #[arg(short, long)]
     pub input_file: Vec<String>,
 
@@ -27,6 +38,63 @@ pub struct CommandLineArgs {
     #[arg(short, long, default_value_t = num_cpus::get(), value_parser = Self::parse_semaphore_permits)]
     pub jobs: usize,
 
+    /// Keep output in the same order as inputs.
+    ///
+    /// Outputs of commands that finish early are buffered until all previous outputs are written.
+    #[arg(short, long)]
+    pub keep_order: bool,
+
+    /// Write output of running commands as complete lines arrive instead of buffering all output.
+    ///
+    /// Lines from different commands are never mixed, but lines from different commands may be interleaved.
+    #[arg(long, conflicts_with = "keep_order")]
+    pub line_buffer: bool,
+
+    /// Run each command with up to this many inputs instead of one.
+    ///
+    /// Arguments with replacement strings for an input, e.g. {} or {1.}, are repeated
+    /// once per input.  Otherwise all inputs are appended to the command.
+    #[arg(short('n'), long, value_parser = Self::parse_batch_size, conflicts_with = "regex")]
+    pub max_args: Option<usize>,
+
+    /// Run each command with up to this many input lines.  An input spanning several
+    /// lines, e.g. a quoted CSV record, counts all of its lines.
+    #[arg(short('L'), long, value_parser = Self::parse_batch_size, conflicts_with_all = ["regex", "max_args"])]
+    pub max_lines: Option<usize>,
+
+    /// Run each command with as many inputs as fit within the command line length limit.
+    ///
+    /// Can be combined with -n or -L to also limit the inputs per command.
+    #[arg(short('X'), long, conflicts_with = "regex")]
+    pub xargs: bool,
+
+    /// Split inputs into blocks of records and write each block to the stdin of a command.
+    ///
+    /// Outputs are kept in the same order as inputs.
+    #[arg(long, conflicts_with_all = ["line_buffer", "regex", "colsep", "csv", "jsonl", "max_args", "max_lines", "xargs"])]
+    pub pipe: bool,
+
+    /// Size of blocks in --pipe mode, e.g. 512K, 1M or 1G.  Blocks end at the first record
+    /// boundary after this size.
+    #[arg(long, default_value = "1M", value_parser = Self::parse_byte_size, requires = "pipe")]
+    pub block_size: usize,
+
+    /// Start of each record in --pipe mode.  Escapes \n, \r, \t, \0 and \\ are supported.
+    #[arg(long, default_value = "", value_parser = Self::parse_record_separator, requires = "pipe")]
+    pub recstart: RecordSeparator,
+
+    /// End of each record in --pipe mode.  Escapes \n, \r, \t, \0 and \\ are supported.
+    #[arg(long, default_value = "\\n", value_parser = Self::parse_record_separator, requires = "pipe")]
+    pub recend: RecordSeparator,
+
+    /// Number of records per block in --pipe mode instead of --block-size.
+    #[arg(short('N'), long, value_parser = Self::parse_batch_size, requires = "pipe")]
+    pub max_records: Option<usize>,
+
+    /// Run the command as-is for each input and write the input to its stdin.
+    #[arg(long, conflicts_with_all = ["pipe", "regex", "colsep", "csv", "jsonl", "max_args", "max_lines", "xargs"])]
+    pub stdin_input: bool,
+
     /// Use null separator for reading input files instead of newline.
     #[arg(short('0'), long)]
     pub null_separator: bool,
@@ -36,9 +104,40 @@ pub struct CommandLineArgs {
     pub progress_bar: bool,
 
     /// Apply regex pattern to inputs.
+    ///
+    /// Inputs are matched as bytes.  "." only matches valid UTF-8, use "(?-u:.)" to
+    /// also match bytes of inputs that aren't UTF-8.
     #[arg(short, long)]
     pub regex: Option<String>,
 
+    /// Split input lines into columns separated by this regex.
+    ///
+    /// Columns are available as positional replacement strings {1}, {2}, ... or are
+    /// appended to the command as separate arguments.
+    #[arg(long, conflicts_with = "regex")]
+    pub colsep: Option<String>,
+
+    /// Read inputs as CSV files with a header row.
+    ///
+    /// Fields are available as {name} or {col:name} using column names from the header,
+    /// and as positional replacement strings {1}, {2}, ...  Quoted fields may contain
+    /// commas and newlines.
+    #[arg(long, conflicts_with_all = ["regex", "colsep"])]
+    pub csv: bool,
+
+    /// Read inputs as JSON Lines, one JSON object per line.
+    ///
+    /// Fields are available as {.user.id} or {.files[0]}.  Missing fields and null are
+    /// empty, strings are unquoted and other values are JSON.  An optional "_job" field
+    /// sets options for the job, e.g. {"_job": {"timeout": 5, "env": {"KEY": "value"}}}
+    /// with timeout in seconds.
+    #[arg(long, conflicts_with_all = ["regex", "colsep", "csv"])]
+    pub jsonl: bool,
+
+    /// What to do with --jsonl input lines that are not valid JSON objects.
+    #[arg(long, value_enum, default_value_t = InvalidJsonLines::Skip, requires = "jsonl")]
+    pub invalid_json_lines: InvalidJsonLines,
+
     /// Use shell mode for running commands.
     ///
     /// Each command line is passed to "<shell-path> -c" as a single argument.
@@ -49,10 +148,41 @@ pub struct CommandLineArgs {
     #[arg(short, long, value_parser = Self::parse_timeout_seconds)]
     pub timeout_seconds: Option<f64>,
 
+    /// Signals sent to the process group of a command that times out, separated by commas.
+    ///
+    /// Durations such as "5s" or "500ms" wait for the command to exit before continuing
+    /// with the next signal.  Processes still running at the end of the sequence are killed.
+    #[arg(
+        long,
+        default_value = "TERM,5s,KILL",
+        value_parser = Self::parse_timeout_signals,
+    )]
+    pub timeout_signals: TimeoutSignalSequence,
+
+    /// Number of times to retry a command that fails, times out, or cannot be spawned.
+    ///
+    /// A command keeps its job slot between attempts, and only output of the final
+    /// attempt is written.
+    #[arg(long, default_value_t = 0, conflicts_with = "line_buffer")]
+    pub retries: usize,
+
+    /// Seconds to wait before the first retry, doubling for each later retry plus random jitter.
+    #[arg(long, default_value_t = 0f64, value_parser = Self::parse_retry_delay)]
+    pub retry_delay: f64,
+
     /// Input and output channel capacity, defaults to num cpus * 2
     #[arg(long, default_value_t = num_cpus::get() * 2, value_parser = Self::parse_semaphore_permits)]
     pub channel_capacity: usize,
 
+    /// Output of each stream of a command held in memory, e.g. 512K or 16M.  Output beyond
+    /// this is written to a temporary file until it is printed.
+    #[arg(long, default_value = "1M", value_parser = Self::parse_byte_size)]
+    pub output_memory_limit: usize,
+
+    /// Maximum number of commands running or waiting to be written in keep order mode, defaults to jobs * 2
+    #[arg(long, value_parser = Self::parse_semaphore_permits)]
+    pub keep_order_buffer: Option<usize>,
+
     /// Disable command path cache
     #[arg(long)]
     pub disable_path_cache: bool,
@@ -63,16 +193,93 @@ pub struct CommandLineArgs {
     #[arg(long)]
     pub dry_run: bool,
 
+    /// Format of command output.
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text, conflicts_with_all = ["line_buffer", "tag", "tagstring", "timestamp"])]
+    pub output_format: OutputFormat,
+
+    /// Prefix each output line with the command's input and a tab.
+    #[arg(long)]
+    pub tag: bool,
+
+    /// Prefix each output line with this template and a tab.  Implies --tag.
+    ///
+    /// Has the same replacement strings as commands, e.g. "{}", "{1}", "{.}", "{#}"
+    /// and "{%}", plus "{line}" for the input name and line number.
+    #[arg(long)]
+    pub tagstring: Option<String>,
+
+    /// Prefix each output line with an RFC 3339 timestamp and a tab.
+    #[arg(long)]
+    pub timestamp: bool,
+
+    /// Write a log of completed commands to this file.
+    ///
+    /// Each row records the sequence number, input, start time, runtime,
+    /// exit code, signal, output sizes, and command line.
+    #[arg(long)]
+    pub joblog: Option<String>,
+
+    /// Format of the joblog file.
+    #[arg(long, value_enum, default_value_t = JobLogFormat::Tsv)]
+    pub joblog_format: JobLogFormat,
+
+    /// Write the stdout, stderr, exit code, runtime and command line of each job to its own
+    /// directory under this directory, with an index of all jobs at the top level.
+    #[arg(long, conflicts_with = "line_buffer")]
+    pub results: Option<String>,
+
+    /// Name of the directory of each job under --results.
+    #[arg(long, value_enum, default_value_t = ResultsLayout::Seq, requires = "results")]
+    pub results_layout: ResultsLayout,
+
+    /// Format of the index of all jobs under --results.
+    #[arg(long, value_enum, default_value_t = ResultsIndexFormat::Csv, requires = "results")]
+    pub results_index_format: ResultsIndexFormat,
+
+    /// Skip inputs whose sequence number is already in the joblog.  Requires --joblog.
+    #[arg(long, requires = "joblog", conflicts_with = "resume_failed")]
+    pub resume: bool,
+
+    /// Skip inputs that succeeded in the joblog, rerun inputs that failed or timed out.  Requires --joblog.
+    #[arg(long, requires = "joblog")]
+    pub resume_failed: bool,
+
     /// Path to shell to use for shell mode
     #[arg(long, default_value = Self::default_shell())]
     pub shell_path: String,
 
+    /// Halt policy for stopping early after command failures or successes.
+    ///
+    /// One of "never", "soon,fail=N", "soon,fail=P%", "soon,success=N",
+    /// "now,fail=N", "now,fail=P%", or "now,success=N".
+    ///
+    /// "soon" stops starting new commands and waits for running commands to finish.
+    /// "now" also kills running commands.  Percentages are relative to the total
+    /// number of commands, or to the completed commands while inputs are still being
+    /// read, and only apply once 3 commands have completed.
+    #[arg(long, default_value = "never", value_parser = Self::parse_halt_policy)]
+    pub halt: HaltPolicy,
+
+    /// How linked ::: groups of different lengths are combined.
+    #[arg(long, value_enum, default_value_t = LinkMismatch::Error)]
+    pub link_mismatch: LinkMismatch,
+
     /// Optional command and initial arguments.
     ///
     /// If this contains 1 or more ::: delimiters the cartesian product
-    /// of arguments from all groups are run.
-    #[arg(trailing_var_arg(true))]
-    pub command_and_initial_arguments: Vec<String>,
+    /// of arguments from all groups are run.  A group starting with :::+
+    /// is linked to the previous group: their values are paired up in order
+    /// instead of being combined.
+    ///
+    /// :::: and ::::+ start a group read from the files (or - for stdin) that
+    /// follow, one value per line.
+    ///
+    /// Inputs are appended to the command unless it contains a replacement string:
+    /// {} input, {.} input without extension, {/} basename, {//} dirname,
+    /// {/.} basename without extension, {#} sequence number, {%} job slot.
+    /// {1}, {2.}, {2/} etc. refer to the value from one ::: group or input file.
+    #[arg(trailing_var_arg(true), value_parser = clap::value_parser!(OsString))]
+    pub command_and_initial_arguments: Vec<OsString>,
 }
 
 impl CommandLineArgs {
@@ -93,7 +300,27 @@ impl CommandLineArgs {
     pub fn commands_from_args_mode(&self) -> bool {
         self.command_and_initial_arguments
             .iter()
-            .any(|s| s == COMMANDS_FROM_ARGS_SEPARATOR)
+            .any(|s| ArgumentGroupSeparator::parse(s).is_some())
+    }
+
+    /// Outputs are written in input order with --keep-order and in --pipe mode.
+    pub fn keep_order_mode(&self) -> bool {
+        self.keep_order || self.pipe
+    }
+
+    /// Returns true if several inputs may be run by one command.
+    pub fn batch_mode(&self) -> bool {
+        self.max_args.is_some() || self.max_lines.is_some() || self.xargs
+    }
+
+    pub fn resume_mode(&self) -> bool {
+        self.resume || self.resume_failed
+    }
+
+    pub fn keep_order_buffer_size(&self) -> usize {
+        self.keep_order_buffer
+            .unwrap_or_else(|| self.jobs.saturating_mul(2))
+            .clamp(1, tokio::sync::Semaphore::MAX_PERMITS)
     }
 
     fn parse_semaphore_permits(s: &str) -> Result<usize, String> {
@@ -107,6 +334,59 @@ impl CommandLineArgs {
         }
     }
 
+    fn parse_batch_size(s: &str) -> Result<usize, String> {
+        let value: usize = s.parse().map_err(|_| format!("`{s}` isn't a number"))?;
+        if value > 0 {
+            Ok(value)
+        } else {
+            Err("value not greater than 0".to_string())
+        }
+    }
+
+    fn parse_byte_size(s: &str) -> Result<usize, String> {
+        let (digits, multiplier) = match s.char_indices().last() {
+            Some((i, 'k' | 'K')) => (&s[..i], 1 << 10),
+            Some((i, 'm' | 'M')) => (&s[..i], 1 << 20),
+            Some((i, 'g' | 'G')) => (&s[..i], 1 << 30),
+            _ => (s, 1),
+        };
+
+        let value: usize = digits
+            .parse()
+            .map_err(|_| format!("`{s}` isn't a size such as 512K, 1M or 1G"))?;
+
+        match value.checked_mul(multiplier) {
+            Some(value) if value > 0 => Ok(value),
+            Some(_) => Err("value not greater than 0".to_string()),
+            None => Err(format!("`{s}` is too large")),
+        }
+    }
+
+    fn parse_record_separator(s: &str) -> Result<RecordSeparator, String> {
+        let mut result = Vec::with_capacity(s.len());
+        let mut chars = s.chars();
+
+        while let Some(c) = chars.next() {
+            let c = if c == '\\' {
+                match chars.next() {
+                    Some('n') => '\n',
+                    Some('r') => '\r',
+                    Some('t') => '\t',
+                    Some('0') => '\0',
+                    Some('\\') => '\\',
+                    Some(other) => return Err(format!("unknown escape `\\{other}`")),
+                    None => return Err("trailing backslash".to_string()),
+                }
+            } else {
+                c
+            };
+
+            result.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+        }
+
+        Ok(RecordSeparator(result))
+    }
+
     fn parse_timeout_seconds(s: &str) -> Result<f64, String> {
         let value: f64 = s.parse().map_err(|_| format!("`{s}` isn't a number"))?;
         if value > 0f64 {
@@ -116,6 +396,73 @@ impl CommandLineArgs {
         }
     }
 
+    fn parse_halt_policy(s: &str) -> Result<HaltPolicy, String> {
+        if s == "never" {
+            return Ok(HaltPolicy::Never);
+        }
+
+        let invalid = || format!("`{s}` isn't a valid halt policy");
+
+        let (when, condition) = s.split_once(',').ok_or_else(invalid)?;
+
+        let (condition_type, threshold) = condition.split_once('=').ok_or_else(invalid)?;
+
+        let threshold = if let Some(percent) = threshold.strip_suffix('%') {
+            let percent: f64 = percent.parse().map_err(|_| invalid())?;
+            if !(percent > 0f64 && percent <= 100f64) {
+                return Err(format!("halt percentage `{percent}` not in range (0, 100]"));
+            }
+            HaltThreshold::Percent(percent)
+        } else {
+            let count: u64 = threshold.parse().map_err(|_| invalid())?;
+            if count == 0 {
+                return Err("halt count not greater than 0".to_string());
+            }
+            HaltThreshold::Count(count)
+        };
+
+        let condition = match condition_type {
+            "fail" => HaltCondition::Fail(threshold),
+            "success" => HaltCondition::Success(threshold),
+            _ => return Err(invalid()),
+        };
+
+        match when {
+            "soon" => Ok(HaltPolicy::Soon(condition)),
+            "now" => Ok(HaltPolicy::Now(condition)),
+            _ => Err(invalid()),
+        }
+    }
+
+    fn parse_timeout_signals(s: &str) -> Result<TimeoutSignalSequence, String> {
+        let steps = s
+            .split(',')
+            .map(|step| {
+                let step = step.trim();
+                if step.starts_with(|c: char| c.is_ascii_digit()) {
+                    humantime::parse_duration(step)
+                        .map(TimeoutSignalStep::Wait)
+                        .map_err(|e| format!("`{step}` isn't a valid duration: {e}"))
+                } else {
+                    ChildSignal::from_name(step)
+                        .map(TimeoutSignalStep::Signal)
+                        .ok_or_else(|| format!("`{step}` isn't a supported signal"))
+                }
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(TimeoutSignalSequence(steps))
+    }
+
+    fn parse_retry_delay(s: &str) -> Result<f64, String> {
+        let value: f64 = s.parse().map_err(|_| format!("`{s}` isn't a number"))?;
+        if value >= 0f64 && value.is_finite() {
+            Ok(value)
+        } else {
+            Err("value not a finite number greater than or equal to 0".to_string())
+        }
+    }
+
     fn default_shell() -> &'static str {
         if cfg!(target_os = "windows") {
             if cfg!(feature = "win_cmd_shell") {
@@ -141,6 +488,152 @@ pub enum DiscardOutput {
     All,
 }
 
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub enum LinkMismatch {
+    /// Fail if linked groups have different lengths
+    #[default]
+    Error,
+    /// Reuse values of shorter linked groups from the start
+    Wrap,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub enum InvalidJsonLines {
+    /// Warn and skip invalid lines
+    #[default]
+    Skip,
+    /// Stop reading inputs and exit with an error
+    Fail,
+}
+
+/// Separator starting an argument group in commands from args mode.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ArgumentGroupSeparator {
+    /// Values are paired up with the previous group instead of combined with it.
+    pub linked: bool,
+    /// The following arguments are files (or - for stdin) with one value per line.
+    pub from_files: bool,
+}
+
+impl ArgumentGroupSeparator {
+    pub fn parse(arg: &OsStr) -> Option<Self> {
+        let (linked, from_files) = match arg.to_str()? {
+            COMMANDS_FROM_ARGS_SEPARATOR => (false, false),
+            LINKED_COMMANDS_FROM_ARGS_SEPARATOR => (true, false),
+            ARGUMENT_FILES_SEPARATOR => (false, true),
+            LINKED_ARGUMENT_FILES_SEPARATOR => (true, true),
+            _ => return None,
+        };
+
+        Some(Self { linked, from_files })
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub enum JobLogFormat {
+    /// Tab separated values with a header row, similar to GNU parallel
+    #[default]
+    Tsv,
+    /// One JSON object per line
+    Jsonl,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum HaltPolicy {
+    /// Never halt, run all commands
+    #[default]
+    Never,
+    /// Stop starting new commands, wait for running commands to finish
+    Soon(HaltCondition),
+    /// Stop starting new commands and kill running commands
+    Now(HaltCondition),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HaltCondition {
+    Fail(HaltThreshold),
+    Success(HaltThreshold),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HaltThreshold {
+    Count(u64),
+    Percent(f64),
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub enum OutputFormat {
+    /// Output of commands as written
+    #[default]
+    Text,
+    /// One JSON object per command on stdout with its input, exit status, runtime, stdout
+    /// and stderr, which are base64 encoded if not valid UTF-8.  Logs go to stderr.
+    Json,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub enum ResultsLayout {
+    /// Directory named after the sequence number of the job, e.g. 3
+    #[default]
+    Seq,
+    /// Directories named after each input value, e.g. 1/a.txt/2/b.txt, similar to GNU parallel
+    Args,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub enum ResultsIndexFormat {
+    /// index.csv with a header row
+    #[default]
+    Csv,
+    /// index.jsonl with one JSON object per line
+    Jsonl,
+}
+
+/// Bytes starting or ending a record in --pipe mode.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RecordSeparator(pub Vec<u8>);
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TimeoutSignalSequence(pub Vec<TimeoutSignalStep>);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TimeoutSignalStep {
+    /// Send a signal to the process group
+    Signal(ChildSignal),
+    /// Wait up to this long for the command to exit
+    Wait(std::time::Duration),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ChildSignal {
+    Hup,
+    Int,
+    Quit,
+    Kill,
+    Usr1,
+    Usr2,
+    Term,
+}
+
+impl ChildSignal {
+    /// Parses a signal name such as "TERM" or "SIGTERM", ignoring case.
+    fn from_name(name: &str) -> Option<Self> {
+        let name = name.to_ascii_uppercase();
+        let name = name.strip_prefix("SIG").unwrap_or(&name);
+
+        match name {
+            "HUP" => Some(Self::Hup),
+            "INT" => Some(Self::Int),
+            "QUIT" => Some(Self::Quit),
+            "KILL" => Some(Self::Kill),
+            "USR1" => Some(Self::Usr1),
+            "USR2" => Some(Self::Usr2),
+            "TERM" => Some(Self::Term),
+            _ => None,
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -151,4 +644,91 @@ mod test {
 
         CommandLineArgs::command().debug_assert()
     }
+
+    #[test]
+    fn test_parse_halt_policy() {
+        assert_eq!(
+            CommandLineArgs::parse_halt_policy("never"),
+            Ok(HaltPolicy::Never)
+        );
+        assert_eq!(
+            CommandLineArgs::parse_halt_policy("soon,fail=3"),
+            Ok(HaltPolicy::Soon(HaltCondition::Fail(HaltThreshold::Count(
+                3
+            ))))
+        );
+        assert_eq!(
+            CommandLineArgs::parse_halt_policy("now,fail=25%"),
+            Ok(HaltPolicy::Now(HaltCondition::Fail(
+                HaltThreshold::Percent(25f64)
+            )))
+        );
+        assert_eq!(
+            CommandLineArgs::parse_halt_policy("now,success=1"),
+            Ok(HaltPolicy::Now(HaltCondition::Success(
+                HaltThreshold::Count(1)
+            )))
+        );
+
+        assert!(CommandLineArgs::parse_halt_policy("later,fail=1").is_err());
+        assert!(CommandLineArgs::parse_halt_policy("soon,fail=0").is_err());
+        assert!(CommandLineArgs::parse_halt_policy("soon,fail=101%").is_err());
+        assert!(CommandLineArgs::parse_halt_policy("soon,done=1").is_err());
+        assert!(CommandLineArgs::parse_halt_policy("soon").is_err());
+    }
+
+    #[test]
+    fn test_parse_timeout_signals() {
+        assert_eq!(
+            CommandLineArgs::parse_timeout_signals("TERM,5s,sigkill"),
+            Ok(TimeoutSignalSequence(vec![
+                TimeoutSignalStep::Signal(ChildSignal::Term),
+                TimeoutSignalStep::Wait(std::time::Duration::from_secs(5)),
+                TimeoutSignalStep::Signal(ChildSignal::Kill),
+            ]))
+        );
+        assert_eq!(
+            CommandLineArgs::parse_timeout_signals("INT, 250ms"),
+            Ok(TimeoutSignalSequence(vec![
+                TimeoutSignalStep::Signal(ChildSignal::Int),
+                TimeoutSignalStep::Wait(std::time::Duration::from_millis(250)),
+            ]))
+        );
+
+        assert!(CommandLineArgs::parse_timeout_signals("TERM,5x").is_err());
+        assert!(CommandLineArgs::parse_timeout_signals("STOP").is_err());
+        assert!(CommandLineArgs::parse_timeout_signals("").is_err());
+    }
+
+    #[test]
+    fn test_parse_byte_size() {
+        assert_eq!(CommandLineArgs::parse_byte_size("100"), Ok(100));
+        assert_eq!(CommandLineArgs::parse_byte_size("2k"), Ok(2048));
+        assert_eq!(CommandLineArgs::parse_byte_size("1M"), Ok(1 << 20));
+        assert_eq!(CommandLineArgs::parse_byte_size("1G"), Ok(1 << 30));
+
+        assert!(CommandLineArgs::parse_byte_size("0").is_err());
+        assert!(CommandLineArgs::parse_byte_size("M").is_err());
+        assert!(CommandLineArgs::parse_byte_size("1T").is_err());
+        assert!(CommandLineArgs::parse_byte_size("").is_err());
+    }
+
+    #[test]
+    fn test_parse_record_separator() {
+        assert_eq!(
+            CommandLineArgs::parse_record_separator("\\n"),
+            Ok(RecordSeparator(b"\n".to_vec()))
+        );
+        assert_eq!(
+            CommandLineArgs::parse_record_separator(">\\t\\0\\\\"),
+            Ok(RecordSeparator(b">\t\0\\".to_vec()))
+        );
+        assert_eq!(
+            CommandLineArgs::parse_record_separator(""),
+            Ok(RecordSeparator(vec![]))
+        );
+
+        assert!(CommandLineArgs::parse_record_separator("\\x").is_err());
+        assert!(CommandLineArgs::parse_record_separator("a\\").is_err());
+    }
 }
@@ -1,9 +1,64 @@
-use std::{collections::VecDeque, path::PathBuf};
+use std::{
+    collections::VecDeque,
+    ffi::{OsStr, OsString},
+    path::PathBuf,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
-#[derive(Debug, Eq, PartialEq)]
+/// The job slot replacement string.
+pub const JOB_SLOT: &str = "{%}";
+
+#[derive(Clone, Debug, Eq, PartialEq)]
 pub struct OwnedCommandAndArgs {
     pub command_path: PathBuf,
-    pub args: Vec<String>,
+    pub args: Vec<OsString>,
+    /// Positions of the {%} from the command template in `args`, ordered by argument
+    /// and offset.
+    pub job_slots: Vec<JobSlotPosition>,
+}
+
+/// Byte offset of a {%} in an argument, replaced with the job slot when the job starts.
+/// Input values substituted into the argument may contain "{%}" too, which must be
+/// left alone.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct JobSlotPosition {
+    pub arg: usize,
+    pub offset: usize,
+}
+
+impl OwnedCommandAndArgs {
+    /// Replaces the {%} from the command template with `job_slot`.
+    pub fn replace_job_slots(&mut self, job_slot: usize) {
+        let job_slot = job_slot.to_string();
+
+        // Replace from the end so earlier offsets stay valid.
+        for position in std::mem::take(&mut self.job_slots).into_iter().rev() {
+            let Some(arg) = self.args.get_mut(position.arg) else {
+                continue;
+            };
+
+            let bytes = arg.as_encoded_bytes();
+
+            let Some(rest) = bytes.get(position.offset + JOB_SLOT.len()..) else {
+                continue;
+            };
+
+            let mut replaced = Vec::with_capacity(bytes.len());
+            replaced.extend_from_slice(&bytes[..position.offset]);
+            replaced.extend_from_slice(job_slot.as_bytes());
+            replaced.extend_from_slice(rest);
+
+            *arg = os_string_from_bytes(replaced);
+        }
+    }
+
+    /// Command path and arguments joined by spaces.
+    pub fn command_line(&self) -> String {
+        std::iter::once(self.command_path.to_string_lossy())
+            .chain(self.args.iter().map(|arg| arg.to_string_lossy()))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
 }
 
 impl std::fmt::Display for OwnedCommandAndArgs {
@@ -12,16 +67,80 @@ impl std::fmt::Display for OwnedCommandAndArgs {
     }
 }
 
+/// Converts an input segment to an OsString.  Any bytes are valid on Unix, other
+/// platforms need UTF-8.
+pub fn os_string_from_input(segment: Vec<u8>) -> anyhow::Result<OsString> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStringExt;
+        Ok(OsString::from_vec(segment))
+    }
+
+    #[cfg(not(unix))]
+    {
+        use anyhow::Context;
+        String::from_utf8(segment)
+            .map(OsString::from)
+            .context("invalid UTF-8")
+    }
+}
+
+/// Converts bytes built from `OsStr::as_encoded_bytes` back to an OsString.  Off Unix
+/// inputs are UTF-8, so invalid UTF-8 can't occur and would be replaced.
+pub fn os_string_from_bytes(bytes: Vec<u8>) -> OsString {
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStringExt;
+        OsString::from_vec(bytes)
+    }
+
+    #[cfg(not(unix))]
+    {
+        String::from_utf8(bytes)
+            .unwrap_or_else(|e| String::from_utf8_lossy(e.as_bytes()).into_owned())
+            .into()
+    }
+}
+
+/// Joins values with spaces, e.g. for {} with several input values.
+pub fn join_os_strings<S: AsRef<OsStr>>(values: &[S]) -> OsString {
+    let mut joined = OsString::new();
+
+    for (i, value) in values.iter().enumerate() {
+        if i > 0 {
+            joined.push(" ");
+        }
+        joined.push(value);
+    }
+
+    joined
+}
+
+/// Seconds since the Unix epoch, for start times in the joblog and results index.
+pub fn epoch_seconds(time: SystemTime) -> f64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs_f64()
+}
+
+/// Options for one job that override command line options, e.g. from a --jsonl input.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct JobOptions {
+    pub timeout: Option<Duration>,
+    /// Extra environment variables for the command.
+    pub env: Vec<(String, String)>,
+}
+
 #[derive(thiserror::Error, Debug)]
 pub enum OwnedCommandAndArgsConversionError {
     #[error("empty input")]
     EmptyInput,
 }
 
-impl TryFrom<VecDeque<String>> for OwnedCommandAndArgs {
+impl TryFrom<VecDeque<OsString>> for OwnedCommandAndArgs {
     type Error = OwnedCommandAndArgsConversionError;
 
-    fn try_from(mut deque: VecDeque<String>) -> Result<Self, Self::Error> {
+    fn try_from(mut deque: VecDeque<OsString>) -> Result<Self, Self::Error> {
         let command = deque
             .pop_front()
             .ok_or(OwnedCommandAndArgsConversionError::EmptyInput)?;
@@ -29,14 +148,15 @@ impl TryFrom<VecDeque<String>> for OwnedCommandAndArgs {
         Ok(Self {
             command_path: PathBuf::from(command),
             args: deque.into(),
+            job_slots: vec![],
         })
     }
 }
 
-impl TryFrom<Vec<String>> for OwnedCommandAndArgs {
+impl TryFrom<Vec<OsString>> for OwnedCommandAndArgs {
     type Error = OwnedCommandAndArgsConversionError;
 
-    fn try_from(vec: Vec<String>) -> Result<Self, Self::Error> {
+    fn try_from(vec: Vec<OsString>) -> Result<Self, Self::Error> {
         Self::try_from(VecDeque::from(vec))
     }
 }
@@ -1,8 +1,14 @@
+mod batch;
+mod block_reader;
 mod buffered_reader;
 mod task;
 
+pub use self::buffered_reader::BufferedInputReader;
+
 use anyhow::Context;
 
+use itertools::Itertools;
+
 use tokio::{
     sync::mpsc::{channel, Receiver},
     task::JoinHandle,
@@ -10,9 +16,15 @@ use tokio::{
 
 use tracing::debug;
 
-use std::sync::Arc;
+use std::{ffi::OsString, ops::RangeInclusive, sync::Arc};
 
-use crate::{command_line_args::CommandLineArgs, common::OwnedCommandAndArgs, progress::Progress};
+use crate::{
+    command::CommandMetrics,
+    command_line_args::CommandLineArgs,
+    common::{JobOptions, OwnedCommandAndArgs},
+    parser,
+    progress::Progress,
+};
 
 #[derive(Debug, Clone, Copy)]
 pub enum BufferedInput {
@@ -46,45 +58,88 @@ impl std::fmt::Display for Input {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct InputLineNumber {
     pub input: Input,
-    pub line_number: usize,
+    /// More than one line for a CSV record spanning lines or a batch of inputs.
+    pub line_numbers: RangeInclusive<usize>,
+}
+
+impl InputLineNumber {
+    pub fn new(input: Input, line_number: usize) -> Self {
+        Self {
+            input,
+            line_numbers: line_number..=line_number,
+        }
+    }
+
+    pub fn line_count(&self) -> usize {
+        self.line_numbers.end() + 1 - self.line_numbers.start()
+    }
 }
 
 impl std::fmt::Display for InputLineNumber {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}:{}", self.input, self.line_number)
+        let (start, end) = (self.line_numbers.start(), self.line_numbers.end());
+
+        if start == end {
+            write!(f, "{}:{}", self.input, start)
+        } else {
+            write!(f, "{}:{}-{}", self.input, start, end)
+        }
     }
 }
 
 enum InputList {
-    BufferedInputList(Vec<BufferedInput>),
+    BufferedInputs(Vec<BufferedInput>),
+
+    /// Input files read line by line side by side, for positional replacement strings.
+    SideBySideBufferedInputs(Vec<BufferedInput>),
 
     CommandLineArgs,
+
+    /// Inputs split into blocks written to the stdin of commands in --pipe mode.
+    Pipe(Vec<BufferedInput>),
+}
+
+fn buffered_input_list(command_line_args: &'static CommandLineArgs) -> Vec<BufferedInput> {
+    if command_line_args.input_file.is_empty() {
+        return vec![BufferedInput::Stdin];
+    }
+
+    command_line_args
+        .input_file
+        .iter()
+        .map(|input_name| {
+            if input_name == "-" {
+                BufferedInput::Stdin
+            } else {
+                BufferedInput::File {
+                    file_name: input_name,
+                }
+            }
+        })
+        .collect_vec()
 }
 
 fn build_input_list(command_line_args: &'static CommandLineArgs) -> InputList {
-    if command_line_args.commands_from_args_mode() {
+    if command_line_args.pipe {
+        InputList::Pipe(buffered_input_list(command_line_args))
+    } else if command_line_args.commands_from_args_mode() {
         InputList::CommandLineArgs
-    } else if command_line_args.input_file.is_empty() {
-        InputList::BufferedInputList(vec![BufferedInput::Stdin])
     } else {
-        InputList::BufferedInputList(
-            command_line_args
-                .input_file
-                .iter()
-                .map(|input_name| {
-                    if input_name == "-" {
-                        BufferedInput::Stdin
-                    } else {
-                        BufferedInput::File {
-                            file_name: input_name,
-                        }
-                    }
-                })
-                .collect(),
-        )
+        let buffered_inputs = buffered_input_list(command_line_args);
+
+        if buffered_inputs.len() > 1
+            && !command_line_args.stdin_input
+            && !command_line_args.csv
+            && !command_line_args.jsonl
+            && parser::command_uses_positional_replacement_strings(command_line_args)
+        {
+            InputList::SideBySideBufferedInputs(buffered_inputs)
+        } else {
+            InputList::BufferedInputs(buffered_inputs)
+        }
     }
 }
 
@@ -92,17 +147,25 @@ fn build_input_list(command_line_args: &'static CommandLineArgs) -> InputList {
 pub struct InputMessage {
     pub command_and_args: OwnedCommandAndArgs,
     pub input_line_number: InputLineNumber,
+    /// 1-based position of this input across all inputs.
+    pub sequence_number: usize,
+    /// Input values the command was built from: the input line, or one value per ::: group.
+    pub input_values: Vec<OsString>,
+    pub job_options: JobOptions,
+    /// Bytes written to the stdin of the command, which otherwise gets no stdin.
+    pub stdin: Option<Arc<[u8]>>,
 }
 
 pub struct InputProducer {
-    sender_task_join_handle: JoinHandle<()>,
+    sender_task_join_handle: JoinHandle<anyhow::Result<()>>,
     receiver: Receiver<InputMessage>,
 }
 
 impl InputProducer {
-    pub fn new(
+    pub async fn new(
         command_line_args: &'static CommandLineArgs,
         progress: &Arc<Progress>,
+        command_metrics: &Arc<CommandMetrics>,
     ) -> anyhow::Result<Self> {
         let (sender, receiver) = channel(command_line_args.channel_capacity);
         debug!(
@@ -110,7 +173,9 @@ impl InputProducer {
             command_line_args.channel_capacity
         );
 
-        let input_sender_task = task::InputSenderTask::new(command_line_args, sender, progress)?;
+        let input_sender_task =
+            task::InputSenderTask::new(command_line_args, sender, progress, command_metrics)
+                .await?;
 
         let sender_task_join_handle = tokio::spawn(input_sender_task.run());
 
@@ -124,10 +189,21 @@ impl InputProducer {
         &mut self.receiver
     }
 
+    /// Stops reading inputs, even if the sender task is waiting for more input.
+    pub fn stop(&mut self) {
+        self.receiver.close();
+        self.sender_task_join_handle.abort();
+    }
+
     pub async fn wait_for_completion(self) -> anyhow::Result<()> {
-        self.sender_task_join_handle
-            .await
-            .context("InputProducer::wait_for_completion: sender_task_join_handle.await error")?;
+        match self.sender_task_join_handle.await {
+            Err(e) if e.is_cancelled() => {
+                debug!("InputProducer::wait_for_completion: sender task cancelled");
+            }
+            result => result.context(
+                "InputProducer::wait_for_completion: sender_task_join_handle.await error",
+            )??,
+        }
 
         Ok(())
     }
@@ -0,0 +1,313 @@
+use std::mem;
+
+use crate::{
+    command_line_args::CommandLineArgs,
+    common::JobOptions,
+    parser::{BatchInput, BatchLengthEstimator},
+};
+
+use super::InputLineNumber;
+
+/// Command line length limit for -X where the system limit can't be queried.  Linux
+/// limits a single argument, such as the command of --shell, to 128 KiB so stay below
+/// that with some room to spare.
+const MAX_COMMAND_LINE_BYTES: usize = if cfg!(windows) { 8 * 1024 } else { 126 * 1024 };
+
+/// Room left below the system limit, as GNU xargs does.
+const HEADROOM_BYTES: usize = 2048;
+
+/// Command line length limit for -X: the system's ARG_MAX less the size of the
+/// environment, like GNU parallel and xargs.
+fn max_command_line_bytes(command_line_args: &CommandLineArgs) -> usize {
+    let Some(arg_max) = arg_max() else {
+        return MAX_COMMAND_LINE_BYTES;
+    };
+
+    let environment_bytes: usize = std::env::vars_os()
+        .map(|(key, value)| key.len() + value.len() + 2 + mem::size_of::<usize>())
+        .sum();
+
+    let max_bytes = arg_max.saturating_sub(environment_bytes + HEADROOM_BYTES);
+
+    // The shell command is a single argument.
+    if command_line_args.shell && cfg!(target_os = "linux") {
+        max_bytes.min(MAX_COMMAND_LINE_BYTES)
+    } else {
+        max_bytes
+    }
+}
+
+#[cfg(unix)]
+fn arg_max() -> Option<usize> {
+    use nix::unistd::{sysconf, SysconfVar};
+
+    let arg_max = sysconf(SysconfVar::ARG_MAX).ok()??;
+
+    usize::try_from(arg_max).ok()
+}
+
+#[cfg(not(unix))]
+fn arg_max() -> Option<usize> {
+    None
+}
+
+/// One parsed input waiting to be batched.
+pub struct BatchEntry {
+    pub input_line_number: InputLineNumber,
+    pub batch_input: BatchInput,
+    pub job_options: JobOptions,
+}
+
+/// Inputs run by one command.
+pub struct Batch {
+    /// Lines of all inputs in the batch.
+    pub input_line_number: InputLineNumber,
+    pub inputs: Vec<BatchInput>,
+    pub job_options: JobOptions,
+}
+
+/// Collects inputs into batches for -n, -L and -X.  Inputs with different job options
+/// are never batched together.
+pub struct Batcher {
+    max_inputs: Option<usize>,
+    max_lines: Option<usize>,
+    max_bytes: Option<usize>,
+    length_estimator: BatchLengthEstimator,
+    entries: Vec<BatchEntry>,
+    lines: usize,
+    bytes: usize,
+}
+
+impl Batcher {
+    /// Returns None if inputs are not batched.
+    pub fn new(command_line_args: &CommandLineArgs) -> Option<Self> {
+        if !command_line_args.batch_mode() {
+            return None;
+        }
+
+        let length_estimator = BatchLengthEstimator::new(command_line_args);
+
+        Some(Self {
+            max_inputs: command_line_args.max_args,
+            max_lines: command_line_args.max_lines,
+            max_bytes: command_line_args
+                .xargs
+                .then(|| max_command_line_bytes(command_line_args)),
+            bytes: length_estimator.template_bytes(),
+            length_estimator,
+            entries: vec![],
+            lines: 0,
+        })
+    }
+
+    fn fits(&self, entry: &BatchEntry, input_bytes: usize) -> bool {
+        let Some(first_entry) = self.entries.first() else {
+            return true;
+        };
+
+        first_entry.job_options == entry.job_options
+            && self
+                .max_inputs
+                .is_none_or(|max_inputs| self.entries.len() < max_inputs)
+            && self.max_lines.is_none_or(|max_lines| {
+                self.lines + entry.input_line_number.line_count() <= max_lines
+            })
+            && self
+                .max_bytes
+                .is_none_or(|max_bytes| self.bytes + input_bytes <= max_bytes)
+    }
+
+    /// Adds an input, returning the current batch first if the input does not fit in it.
+    pub fn push(&mut self, entry: BatchEntry) -> Option<Batch> {
+        let input_bytes = self
+            .length_estimator
+            .input_bytes(entry.batch_input.input_values());
+
+        let batch = if self.fits(&entry, input_bytes) {
+            None
+        } else {
+            self.flush()
+        };
+
+        self.lines += entry.input_line_number.line_count();
+        self.bytes += input_bytes;
+        self.entries.push(entry);
+
+        batch
+    }
+
+    /// Returns true if no more inputs fit in the current batch.
+    pub fn is_full(&self) -> bool {
+        self.max_inputs
+            .is_some_and(|max_inputs| self.entries.len() >= max_inputs)
+            || self
+                .max_lines
+                .is_some_and(|max_lines| self.lines >= max_lines)
+    }
+
+    /// Takes the current batch, None if it is empty.
+    pub fn flush(&mut self) -> Option<Batch> {
+        let entries = mem::take(&mut self.entries);
+
+        self.lines = 0;
+        self.bytes = self.length_estimator.template_bytes();
+
+        let first_entry = entries.first()?;
+        let last_entry = entries.last()?;
+
+        let input_line_number = InputLineNumber {
+            input: first_entry.input_line_number.input,
+            line_numbers: *first_entry.input_line_number.line_numbers.start()
+                ..=*last_entry.input_line_number.line_numbers.end(),
+        };
+
+        let job_options = first_entry.job_options.clone();
+
+        Some(Batch {
+            input_line_number,
+            inputs: entries.into_iter().map(|entry| entry.batch_input).collect(),
+            job_options,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::ops::RangeInclusive;
+
+    use crate::input::{BufferedInput, Input};
+
+    fn entry(line_numbers: RangeInclusive<usize>, value: &str) -> BatchEntry {
+        BatchEntry {
+            input_line_number: InputLineNumber {
+                input: Input::Buffered(BufferedInput::Stdin),
+                line_numbers,
+            },
+            batch_input: BatchInput::new(vec![value.into()]),
+            job_options: JobOptions::default(),
+        }
+    }
+
+    fn batch_values(batch: Batch) -> (String, Vec<String>) {
+        (
+            batch.input_line_number.to_string(),
+            batch
+                .inputs
+                .into_iter()
+                .flat_map(BatchInput::into_input_values)
+                .map(|value| value.to_string_lossy().into_owned())
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn test_max_inputs() {
+        let command_line_args = CommandLineArgs {
+            max_args: Some(2),
+            ..Default::default()
+        };
+
+        let mut batcher = Batcher::new(&command_line_args).unwrap();
+
+        assert!(batcher.push(entry(1..=1, "a")).is_none());
+        assert!(!batcher.is_full());
+        assert!(batcher.push(entry(2..=2, "b")).is_none());
+        assert!(batcher.is_full());
+
+        assert_eq!(
+            batch_values(batcher.flush().unwrap()),
+            ("stdin:1-2".to_owned(), vec!["a".to_owned(), "b".to_owned()])
+        );
+
+        assert!(batcher.push(entry(3..=3, "c")).is_none());
+
+        assert_eq!(
+            batch_values(batcher.flush().unwrap()),
+            ("stdin:3".to_owned(), vec!["c".to_owned()])
+        );
+        assert!(batcher.flush().is_none());
+    }
+
+    #[test]
+    fn test_max_lines() {
+        let command_line_args = CommandLineArgs {
+            max_lines: Some(3),
+            ..Default::default()
+        };
+
+        let mut batcher = Batcher::new(&command_line_args).unwrap();
+
+        assert!(batcher.push(entry(1..=1, "a")).is_none());
+
+        let batch = batcher.push(entry(2..=4, "b")).unwrap();
+
+        assert_eq!(
+            batch_values(batch),
+            ("stdin:1".to_owned(), vec!["a".to_owned()])
+        );
+        assert!(batcher.is_full());
+    }
+
+    #[test]
+    fn test_job_options_not_batched_together() {
+        let command_line_args = CommandLineArgs {
+            xargs: true,
+            ..Default::default()
+        };
+
+        let mut batcher = Batcher::new(&command_line_args).unwrap();
+
+        let mut entry_with_timeout = entry(2..=2, "b");
+        entry_with_timeout.job_options.timeout = Some(std::time::Duration::from_secs(1));
+
+        assert!(batcher.push(entry(1..=1, "a")).is_none());
+
+        let batch = batcher.push(entry_with_timeout).unwrap();
+
+        assert_eq!(batch.job_options, JobOptions::default());
+        assert_eq!(
+            batcher.flush().unwrap().job_options.timeout,
+            Some(std::time::Duration::from_secs(1))
+        );
+    }
+
+    #[test]
+    fn test_max_command_line_bytes() {
+        let command_line_args = CommandLineArgs {
+            xargs: true,
+            shell: true,
+            command_and_initial_arguments: vec!["echo".into()],
+            ..Default::default()
+        };
+
+        let mut batcher = Batcher::new(&command_line_args).unwrap();
+
+        let value = "x".repeat(1000);
+
+        let mut batches: Vec<Batch> = (1..=1000)
+            .filter_map(|line_number| batcher.push(entry(line_number..=line_number, &value)))
+            .collect();
+        batches.extend(batcher.flush());
+
+        assert_eq!(batches.len(), 8);
+        assert!(batches.iter().all(|batch| batch.inputs.len() <= 128));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_max_command_line_bytes_from_arg_max() {
+        let arg_max = arg_max().unwrap();
+
+        let max_bytes = max_command_line_bytes(&CommandLineArgs::default());
+
+        assert!(max_bytes > 0);
+        assert!(max_bytes < arg_max);
+    }
+
+    #[test]
+    fn test_not_batch_mode() {
+        assert!(Batcher::new(&CommandLineArgs::default()).is_none());
+    }
+}
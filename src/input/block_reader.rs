@@ -0,0 +1,263 @@
+use anyhow::bail;
+
+use tokio::io::AsyncBufReadExt;
+
+use crate::command_line_args::CommandLineArgs;
+
+use super::{
+    buffered_reader::{AsyncBufReadBox, BufferedInputReader},
+    BufferedInput, Input, InputLineNumber,
+};
+
+/// Returns an error if records can't be told apart in --pipe mode.
+pub fn check_record_separators(command_line_args: &CommandLineArgs) -> anyhow::Result<()> {
+    if command_line_args.recstart.0.is_empty() && command_line_args.recend.0.is_empty() {
+        bail!("--recstart and --recend can't both be empty");
+    }
+
+    Ok(())
+}
+
+/// Splits input bytes into blocks along record boundaries for --pipe mode.
+///
+/// A record boundary is a position preceded by `recend` and followed by `recstart`.
+/// A block ends at the first boundary at or after `block_size` bytes, or after
+/// `max_records` records if set.
+struct BlockSplitter {
+    block_size: usize,
+    max_records: Option<usize>,
+    recstart: Vec<u8>,
+    recend: Vec<u8>,
+    buffer: Vec<u8>,
+    /// Next position in `buffer` to check for a record boundary.
+    search_position: usize,
+    /// Records found in `buffer` before `search_position`.
+    records: usize,
+}
+
+impl BlockSplitter {
+    fn new(
+        block_size: usize,
+        max_records: Option<usize>,
+        recstart: Vec<u8>,
+        recend: Vec<u8>,
+    ) -> Self {
+        Self {
+            block_size,
+            max_records,
+            recstart,
+            recend,
+            buffer: vec![],
+            search_position: 1,
+            records: 0,
+        }
+    }
+
+    fn extend(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    fn is_boundary(&self, position: usize) -> bool {
+        self.buffer[..position].ends_with(&self.recend)
+            && self.buffer[position..].starts_with(&self.recstart)
+    }
+
+    /// Returns the end of the next complete block in `buffer`, if any.
+    fn find_block_end(&mut self) -> Option<usize> {
+        if self.max_records.is_none() {
+            self.search_position = self.search_position.max(self.block_size);
+        }
+
+        // A boundary can't be recognized until enough bytes follow it to match recstart.
+        while self.search_position + self.recstart.len() <= self.buffer.len()
+            && self.search_position < self.buffer.len()
+        {
+            let position = self.search_position;
+            self.search_position += 1;
+
+            if !self.is_boundary(position) {
+                continue;
+            }
+
+            match self.max_records {
+                None => return Some(position),
+                Some(max_records) => {
+                    self.records += 1;
+                    if self.records >= max_records {
+                        return Some(position);
+                    }
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Returns the next block, or at `eof` whatever remains.
+    fn next_block(&mut self, eof: bool) -> Option<Vec<u8>> {
+        let block_end = match self.find_block_end() {
+            Some(block_end) => block_end,
+            None if eof && !self.buffer.is_empty() => self.buffer.len(),
+            None => return None,
+        };
+
+        let remaining = self.buffer.split_off(block_end);
+        let block = std::mem::replace(&mut self.buffer, remaining);
+
+        self.search_position = 1;
+        self.records = 0;
+
+        Some(block)
+    }
+}
+
+/// Reads blocks of records from a buffered input in --pipe mode.
+pub struct BlockReader {
+    buffered_input: BufferedInput,
+    buf_reader: AsyncBufReadBox,
+    splitter: BlockSplitter,
+    eof: bool,
+    /// Newlines in the blocks returned so far.
+    newlines: usize,
+}
+
+impl BlockReader {
+    pub async fn new(
+        buffered_input: BufferedInput,
+        command_line_args: &CommandLineArgs,
+    ) -> anyhow::Result<Self> {
+        let splitter = BlockSplitter::new(
+            command_line_args.block_size,
+            command_line_args.max_records,
+            command_line_args.recstart.0.clone(),
+            command_line_args.recend.0.clone(),
+        );
+
+        let buf_reader = BufferedInputReader::create_buf_reader(buffered_input).await?;
+
+        Ok(Self {
+            buffered_input,
+            buf_reader,
+            splitter,
+            eof: false,
+            newlines: 0,
+        })
+    }
+
+    /// Returns the next block with the range of lines it spans.
+    pub async fn next_block(&mut self) -> anyhow::Result<Option<(InputLineNumber, Vec<u8>)>> {
+        loop {
+            if let Some(block) = self.splitter.next_block(self.eof) {
+                return Ok(Some((self.input_line_number(&block), block)));
+            }
+
+            if self.eof {
+                return Ok(None);
+            }
+
+            let bytes = self.buf_reader.fill_buf().await?;
+            if bytes.is_empty() {
+                self.eof = true;
+            } else {
+                let len = bytes.len();
+                self.splitter.extend(bytes);
+                self.buf_reader.consume(len);
+            }
+        }
+    }
+
+    fn input_line_number(&mut self, block: &[u8]) -> InputLineNumber {
+        let newlines = block.iter().filter(|&&b| b == b'\n').count();
+
+        let first_line = self.newlines + 1;
+        let last_line = if block.ends_with(b"\n") {
+            self.newlines + newlines
+        } else {
+            self.newlines + newlines + 1
+        };
+
+        self.newlines += newlines;
+
+        InputLineNumber {
+            input: Input::Buffered(self.buffered_input),
+            line_numbers: first_line..=last_line.max(first_line),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn split_blocks(
+        input: &[u8],
+        chunk_size: usize,
+        block_size: usize,
+        max_records: Option<usize>,
+        recstart: &[u8],
+        recend: &[u8],
+    ) -> Vec<Vec<u8>> {
+        let mut splitter =
+            BlockSplitter::new(block_size, max_records, recstart.into(), recend.into());
+
+        let mut blocks = vec![];
+
+        for chunk in input.chunks(chunk_size) {
+            splitter.extend(chunk);
+            while let Some(block) = splitter.next_block(false) {
+                blocks.push(block);
+            }
+        }
+
+        while let Some(block) = splitter.next_block(true) {
+            blocks.push(block);
+        }
+
+        blocks
+    }
+
+    #[test]
+    fn test_block_size() {
+        for chunk_size in [1, 3, 100] {
+            assert_eq!(
+                split_blocks(b"a\nbb\nccc\ndddd\ne", chunk_size, 4, None, b"", b"\n"),
+                vec![
+                    b"a\nbb\n".to_vec(),
+                    b"ccc\n".to_vec(),
+                    b"dddd\n".to_vec(),
+                    b"e".to_vec()
+                ],
+            );
+        }
+
+        assert_eq!(
+            split_blocks(b"a\nb\n", 100, 1 << 20, None, b"", b"\n"),
+            vec![b"a\nb\n".to_vec()],
+        );
+        assert!(split_blocks(b"", 100, 4, None, b"", b"\n").is_empty());
+    }
+
+    #[test]
+    fn test_max_records() {
+        for chunk_size in [1, 2, 100] {
+            assert_eq!(
+                split_blocks(b"1\n2\n3\n4\n5\n", chunk_size, 1, Some(2), b"", b"\n"),
+                vec![b"1\n2\n".to_vec(), b"3\n4\n".to_vec(), b"5\n".to_vec()],
+            );
+        }
+    }
+
+    #[test]
+    fn test_recstart() {
+        for chunk_size in [1, 4, 100] {
+            assert_eq!(
+                split_blocks(b">a\nx\n>b\ny\n>c\n", chunk_size, 1, None, b">", b""),
+                vec![b">a\nx\n".to_vec(), b">b\ny\n".to_vec(), b">c\n".to_vec()],
+            );
+            assert_eq!(
+                split_blocks(b">a\n>>b\n>c\n", chunk_size, 1, Some(2), b">", b"\n"),
+                vec![b">a\n>>b\n".to_vec(), b">c\n".to_vec()],
+            );
+        }
+    }
+}
@@ -6,11 +6,13 @@ use crate::command_line_args::CommandLineArgs;
 
 use super::{BufferedInput, Input, InputLineNumber};
 
-type AsyncBufReadBox = Box<dyn AsyncBufRead + Unpin + Send>;
+pub(super) type AsyncBufReadBox = Box<dyn AsyncBufRead + Unpin + Send>;
 
 pub struct BufferedInputReader {
     buffered_input: BufferedInput,
     split: Split<AsyncBufReadBox>,
+    line_separator: u8,
+    csv: bool,
     next_line_number: usize,
 }
 
@@ -32,11 +34,15 @@ impl BufferedInputReader {
         Ok(Self {
             buffered_input,
             split,
+            line_separator,
+            csv: command_line_args.csv,
             next_line_number: 0,
         })
     }
 
-    async fn create_buf_reader(buffered_input: BufferedInput) -> anyhow::Result<AsyncBufReadBox> {
+    pub(super) async fn create_buf_reader(
+        buffered_input: BufferedInput,
+    ) -> anyhow::Result<AsyncBufReadBox> {
         match buffered_input {
             BufferedInput::Stdin => {
                 let buf_reader = BufReader::new(tokio::io::stdin());
@@ -54,21 +60,83 @@ impl BufferedInputReader {
         }
     }
 
+    /// Returns the next segment with the number of the line it starts on.  In --csv mode
+    /// a segment is a whole record, which continues on the next line while a quoted field
+    /// is open.
     pub async fn next_segment(&mut self) -> anyhow::Result<Option<(InputLineNumber, Vec<u8>)>> {
         let segment = self.split.next_segment().await?;
 
         match segment {
             None => Ok(None),
-            Some(segment) => {
+            Some(mut segment) => {
                 self.next_line_number += 1;
 
-                let input_line_number = InputLineNumber {
-                    input: Input::Buffered(self.buffered_input),
-                    line_number: self.next_line_number,
-                };
+                let mut input_line_number = InputLineNumber::new(
+                    Input::Buffered(self.buffered_input),
+                    self.next_line_number,
+                );
+
+                if self.csv {
+                    let mut csv_field = CsvField::Start.scan(&segment);
+
+                    while csv_field == CsvField::Quoted {
+                        let Some(next_segment) = self.split.next_segment().await? else {
+                            break;
+                        };
+                        self.next_line_number += 1;
+
+                        csv_field = csv_field.scan(&[self.line_separator]).scan(&next_segment);
+                        segment.push(self.line_separator);
+                        segment.extend(next_segment);
+                    }
+
+                    input_line_number.line_numbers =
+                        *input_line_number.line_numbers.start()..=self.next_line_number;
+                }
 
                 Ok(Some((input_line_number, segment)))
             }
         }
     }
 }
+
+/// Where a CSV record is within its current field.  A quote opens a quoted field only
+/// at the start of the field, like the csv crate, so a stray quote inside an unquoted
+/// field doesn't continue the record on the next line.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum CsvField {
+    Start,
+    Unquoted,
+    Quoted,
+    /// A quote in a quoted field, which either ends the field or escapes a quote.
+    QuoteInQuoted,
+}
+
+impl CsvField {
+    fn scan(self, bytes: &[u8]) -> Self {
+        bytes.iter().fold(self, |field, &b| match (field, b) {
+            (Self::Start, b'"') => Self::Quoted,
+            (Self::Quoted, b'"') => Self::QuoteInQuoted,
+            (Self::Quoted, _) => Self::Quoted,
+            (Self::QuoteInQuoted, b'"') => Self::Quoted,
+            (_, b',') => Self::Start,
+            _ => Self::Unquoted,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_csv_field_scan() {
+        assert_eq!(CsvField::Start.scan(b"a,b"), CsvField::Unquoted);
+        assert_eq!(CsvField::Start.scan(b"a,\"b"), CsvField::Quoted);
+        assert_eq!(CsvField::Start.scan(b"a,\"b\nc\""), CsvField::QuoteInQuoted);
+        assert_eq!(CsvField::Start.scan(b"a,\"b \"\"c\"\" d"), CsvField::Quoted);
+        assert_eq!(CsvField::Start.scan(b"a,\"\","), CsvField::Start);
+        assert_eq!(CsvField::Start.scan(b"5\" screen,b"), CsvField::Unquoted);
+        assert_eq!(CsvField::Start.scan(b"a,5\" screen"), CsvField::Unquoted);
+    }
+}
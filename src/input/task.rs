@@ -4,43 +4,230 @@ use tokio::sync::mpsc::Sender;
 
 use tracing::{debug, instrument, warn};
 
-use std::sync::Arc;
+use std::{
+    ffi::OsString,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
 
-use crate::{command_line_args::CommandLineArgs, parser::Parser, progress::Progress};
+use crate::{
+    command::CommandMetrics,
+    command_line_args::{CommandLineArgs, InvalidJsonLines},
+    common::{os_string_from_bytes, JobOptions, OwnedCommandAndArgs},
+    parser::{buffered::BufferedInputLineParser, BatchInput, CsvHeader, InvalidJsonError, Parser},
+    progress::Progress,
+};
 
 use super::{
-    buffered_reader::BufferedInputReader, BufferedInput, Input, InputLineNumber, InputList,
-    InputMessage,
+    batch::{Batch, BatchEntry, Batcher},
+    block_reader::{self, BlockReader},
+    buffered_reader::BufferedInputReader,
+    BufferedInput, Input, InputLineNumber, InputList, InputMessage,
 };
 
+/// An invalid --jsonl input line with --invalid-json-lines=fail, which stops all inputs.
+#[derive(thiserror::Error, Debug)]
+#[error("invalid JSON line {input_line_number}: {error:#}")]
+struct InvalidJsonLineError {
+    input_line_number: InputLineNumber,
+    error: anyhow::Error,
+}
+
 pub struct InputSenderTask {
     sender: Sender<InputMessage>,
     command_line_args: &'static CommandLineArgs,
     progress: Arc<Progress>,
+    command_metrics: Arc<CommandMetrics>,
     parser: Parser,
+    next_sequence_number: AtomicUsize,
 }
 
 impl InputSenderTask {
-    pub fn new(
+    pub async fn new(
         command_line_args: &'static CommandLineArgs,
         sender: Sender<InputMessage>,
         progress: &Arc<Progress>,
+        command_metrics: &Arc<CommandMetrics>,
     ) -> anyhow::Result<Self> {
-        let parser = Parser::new(command_line_args)?;
+        let parser = Parser::new(command_line_args).await?;
         Ok(Self {
             sender,
             command_line_args,
             progress: Arc::clone(progress),
+            command_metrics: Arc::clone(command_metrics),
             parser,
+            next_sequence_number: AtomicUsize::new(1),
         })
     }
 
-    async fn send(&self, input_message: InputMessage) {
-        self.progress.increment_total_commands(1);
+    /// Sequence number the next sent input will get, used for {#} while parsing.
+    fn peek_sequence_number(&self) -> usize {
+        self.next_sequence_number.load(Ordering::SeqCst)
+    }
 
+    fn next_sequence_number(&self) -> usize {
+        self.next_sequence_number.fetch_add(1, Ordering::SeqCst)
+    }
+
+    /// Returns false if the receiver has been closed and no more inputs should be sent.
+    async fn send(&self, input_message: InputMessage) -> bool {
         if let Err(e) = self.sender.send(input_message).await {
-            warn!("input sender send error: {}", e);
+            debug!("input sender send error: {}", e);
+            return false;
         }
+
+        true
+    }
+
+    fn fail_on_invalid_json_lines(&self) -> bool {
+        self.command_line_args.jsonl
+            && self.command_line_args.invalid_json_lines == InvalidJsonLines::Fail
+    }
+
+    /// Warns about an input that can't be parsed and counts it as a failure of the run,
+    /// or returns an error if it should stop all inputs.
+    fn skip_invalid_input(
+        &self,
+        input_line_number: InputLineNumber,
+        error: anyhow::Error,
+    ) -> anyhow::Result<()> {
+        if self.fail_on_invalid_json_lines() && error.is::<InvalidJsonError>() {
+            return Err(InvalidJsonLineError {
+                input_line_number,
+                error,
+            }
+            .into());
+        }
+
+        warn!(
+            "skipping input {} that can't be parsed: {:#}",
+            input_line_number, error
+        );
+
+        self.command_metrics.increment_invalid_inputs();
+
+        Ok(())
+    }
+
+    /// Builds the command for a batch with `parse_batch` and sends it.  Returns false if
+    /// the receiver has been closed.
+    async fn send_batch(
+        &self,
+        batch: Batch,
+        parse_batch: impl Fn(&[BatchInput], usize) -> Option<OwnedCommandAndArgs>,
+    ) -> bool {
+        let Some(command_and_args) = parse_batch(&batch.inputs, self.peek_sequence_number()) else {
+            return true;
+        };
+
+        self.progress.increment_total_commands(1);
+
+        self.send(InputMessage {
+            command_and_args,
+            input_line_number: batch.input_line_number,
+            sequence_number: self.next_sequence_number(),
+            input_values: batch
+                .inputs
+                .into_iter()
+                .flat_map(BatchInput::into_input_values)
+                .collect(),
+            job_options: batch.job_options,
+            stdin: None,
+        })
+        .await
+    }
+
+    /// Adds an input to the current batch, sending batches that are complete.  Returns
+    /// false if the receiver has been closed.
+    async fn push_batch_entry(
+        &self,
+        batcher: &mut Batcher,
+        batch_entry: BatchEntry,
+        parse_batch: impl Fn(&[BatchInput], usize) -> Option<OwnedCommandAndArgs>,
+    ) -> bool {
+        if let Some(batch) = batcher.push(batch_entry) {
+            if !self.send_batch(batch, &parse_batch).await {
+                return false;
+            }
+        }
+
+        if batcher.is_full() {
+            if let Some(batch) = batcher.flush() {
+                return self.send_batch(batch, &parse_batch).await;
+            }
+        }
+
+        true
+    }
+
+    /// Parses one segment of a buffered input, returning None if it should be skipped.
+    fn parse_buffered_segment(
+        &self,
+        parser: &BufferedInputLineParser,
+        csv_header: Option<&CsvHeader>,
+        segment: Vec<u8>,
+    ) -> anyhow::Result<Option<(OwnedCommandAndArgs, Vec<OsString>, JobOptions)>> {
+        let sequence_number = self.peek_sequence_number();
+
+        if let Some(csv_header) = csv_header {
+            let parsed = parser.parse_csv_segment(csv_header, segment, sequence_number)?;
+
+            Ok(parsed.map(|(command_and_args, fields)| {
+                (
+                    command_and_args,
+                    fields.into_iter().map(OsString::from).collect(),
+                    JobOptions::default(),
+                )
+            }))
+        } else if self.command_line_args.jsonl {
+            let parsed = parser.parse_json_segment(segment, sequence_number)?;
+
+            Ok(parsed.map(|(command_and_args, input_line, job_options)| {
+                (command_and_args, vec![input_line.into()], job_options)
+            }))
+        } else {
+            let parsed = parser.parse_segment(segment, sequence_number)?;
+
+            Ok(parsed.map(|(command_and_args, input_line)| {
+                (command_and_args, vec![input_line], JobOptions::default())
+            }))
+        }
+    }
+
+    /// Sends the command run as-is with `segment` written to its stdin in --stdin-input
+    /// mode.  Returns false if the receiver has been closed.
+    async fn send_stdin_input(
+        &self,
+        parser: &BufferedInputLineParser,
+        input_line_number: InputLineNumber,
+        mut segment: Vec<u8>,
+    ) -> bool {
+        let Some(command_and_args) = parser.parse_stdin_command(self.peek_sequence_number()) else {
+            return true;
+        };
+
+        let input_values = vec![os_string_from_bytes(segment.clone())];
+
+        // The child reads the input like a line of a file.
+        segment.push(if self.command_line_args.null_separator {
+            0u8
+        } else {
+            b'\n'
+        });
+
+        self.progress.increment_total_commands(1);
+
+        self.send(InputMessage {
+            command_and_args,
+            input_line_number,
+            sequence_number: self.next_sequence_number(),
+            input_values,
+            job_options: JobOptions::default(),
+            stdin: Some(segment.into()),
+        })
+        .await
     }
 
     async fn process_one_buffered_input(
@@ -57,6 +244,30 @@ impl InputSenderTask {
 
         let parser = self.parser.buffered_input_line_parser().await;
 
+        let csv_header = if self.command_line_args.csv {
+            match input_reader
+                .next_segment()
+                .await
+                .context("next_segment error")?
+            {
+                Some((input_line_number, segment)) => Some(
+                    parser
+                        .parse_csv_header(segment)
+                        .with_context(|| format!("invalid CSV header {}", input_line_number))?,
+                ),
+                None => return Ok(()),
+            }
+        } else {
+            None
+        };
+
+        // Batches end with each input.
+        let mut batcher = Batcher::new(self.command_line_args);
+
+        let parse_batch = |batch: &[BatchInput], sequence_number| {
+            parser.parse_batch(batch, csv_header.as_ref(), sequence_number)
+        };
+
         loop {
             match input_reader
                 .next_segment()
@@ -64,15 +275,72 @@ impl InputSenderTask {
                 .context("next_segment error")?
             {
                 Some((input_line_number, segment)) => {
-                    let Some(command_and_args) = parser.parse_segment(segment) else {
+                    if let Some(batcher) = &mut batcher {
+                        let (batch_input, job_options) =
+                            match parser.parse_batch_input(segment, csv_header.as_ref()) {
+                                Ok(Some(parsed)) => parsed,
+                                Ok(None) => continue,
+                                Err(error) => {
+                                    self.skip_invalid_input(input_line_number, error)?;
+                                    continue;
+                                }
+                            };
+
+                        let batch_entry = BatchEntry {
+                            input_line_number,
+                            batch_input,
+                            job_options,
+                        };
+
+                        if !self
+                            .push_batch_entry(batcher, batch_entry, parse_batch)
+                            .await
+                        {
+                            return Ok(());
+                        }
+
+                        continue;
+                    }
+
+                    if self.command_line_args.stdin_input {
+                        if !self
+                            .send_stdin_input(parser, input_line_number, segment)
+                            .await
+                        {
+                            break;
+                        }
+
+                        continue;
+                    }
+
+                    let parsed =
+                        match self.parse_buffered_segment(parser, csv_header.as_ref(), segment) {
+                            Ok(parsed) => parsed,
+                            Err(error) => {
+                                self.skip_invalid_input(input_line_number, error)?;
+                                continue;
+                            }
+                        };
+
+                    let Some((command_and_args, input_values, job_options)) = parsed else {
                         continue;
                     };
 
-                    self.send(InputMessage {
-                        command_and_args,
-                        input_line_number,
-                    })
-                    .await;
+                    self.progress.increment_total_commands(1);
+
+                    if !self
+                        .send(InputMessage {
+                            command_and_args,
+                            input_line_number,
+                            sequence_number: self.next_sequence_number(),
+                            input_values,
+                            job_options,
+                            stdin: None,
+                        })
+                        .await
+                    {
+                        break;
+                    }
                 }
                 None => {
                     debug!("input_reader.next_segment EOF");
@@ -81,52 +349,297 @@ impl InputSenderTask {
             }
         }
 
+        if let Some(batch) = batcher.as_mut().and_then(Batcher::flush) {
+            self.send_batch(batch, parse_batch).await;
+        }
+
         Ok(())
     }
 
-    async fn process_command_line_args_input(self) {
+    /// Reads one line from each input per command until all inputs are exhausted.
+    /// Inputs that end early contribute empty values.
+    async fn process_side_by_side_buffered_inputs(
+        &self,
+        buffered_inputs: Vec<BufferedInput>,
+    ) -> anyhow::Result<()> {
+        debug!(
+            "begin process_side_by_side_buffered_inputs buffered_inputs {:?}",
+            buffered_inputs
+        );
+
+        let mut input_readers = Vec::with_capacity(buffered_inputs.len());
+        for &buffered_input in &buffered_inputs {
+            input_readers
+                .push(BufferedInputReader::new(buffered_input, self.command_line_args).await?);
+        }
+
+        let parser = self.parser.buffered_input_line_parser().await;
+
+        let mut batcher = Batcher::new(self.command_line_args);
+
+        let parse_batch = |batch: &[BatchInput], sequence_number| {
+            parser.parse_batch(batch, None, sequence_number)
+        };
+
+        let mut line_number = 0;
+
+        loop {
+            let mut segments = Vec::with_capacity(input_readers.len());
+            let mut any_segment = false;
+
+            for input_reader in &mut input_readers {
+                match input_reader
+                    .next_segment()
+                    .await
+                    .context("next_segment error")?
+                {
+                    Some((_, segment)) => {
+                        any_segment = true;
+                        segments.push(segment);
+                    }
+                    None => segments.push(vec![]),
+                }
+            }
+
+            if !any_segment {
+                debug!("all side by side inputs EOF");
+                break;
+            }
+
+            line_number += 1;
+
+            let input_line_number =
+                InputLineNumber::new(Input::Buffered(buffered_inputs[0]), line_number);
+
+            if let Some(batcher) = &mut batcher {
+                let batch_input = match parser.parse_side_by_side_batch_input(segments) {
+                    Ok(batch_input) => batch_input,
+                    Err(error) => {
+                        self.skip_invalid_input(input_line_number, error)?;
+                        continue;
+                    }
+                };
+
+                let batch_entry = BatchEntry {
+                    input_line_number,
+                    batch_input,
+                    job_options: JobOptions::default(),
+                };
+
+                if !self
+                    .push_batch_entry(batcher, batch_entry, parse_batch)
+                    .await
+                {
+                    return Ok(());
+                }
+
+                continue;
+            }
+
+            let parsed =
+                match parser.parse_side_by_side_segments(segments, self.peek_sequence_number()) {
+                    Ok(parsed) => parsed,
+                    Err(error) => {
+                        self.skip_invalid_input(input_line_number, error)?;
+                        continue;
+                    }
+                };
+
+            let Some((command_and_args, input_values)) = parsed else {
+                continue;
+            };
+
+            self.progress.increment_total_commands(1);
+
+            if !self
+                .send(InputMessage {
+                    command_and_args,
+                    input_line_number,
+                    sequence_number: self.next_sequence_number(),
+                    input_values,
+                    job_options: JobOptions::default(),
+                    stdin: None,
+                })
+                .await
+            {
+                break;
+            }
+        }
+
+        if let Some(batch) = batcher.as_mut().and_then(Batcher::flush) {
+            self.send_batch(batch, parse_batch).await;
+        }
+
+        Ok(())
+    }
+
+    /// Sends each block of records of `buffered_input` to the stdin of a command.
+    async fn process_pipe_input(&self, buffered_input: BufferedInput) -> anyhow::Result<()> {
+        debug!("begin process_pipe_input buffered_input {}", buffered_input);
+
+        let mut block_reader = BlockReader::new(buffered_input, self.command_line_args).await?;
+
+        let parser = self.parser.buffered_input_line_parser().await;
+
+        while let Some((input_line_number, block)) = block_reader
+            .next_block()
+            .await
+            .context("next_block error")?
+        {
+            let Some(command_and_args) = parser.parse_stdin_command(self.peek_sequence_number())
+            else {
+                // Every block gets the same command, so none can be run.
+                break;
+            };
+
+            self.progress.increment_total_commands(1);
+
+            if !self
+                .send(InputMessage {
+                    command_and_args,
+                    input_line_number,
+                    sequence_number: self.next_sequence_number(),
+                    input_values: vec![],
+                    job_options: JobOptions::default(),
+                    stdin: Some(block.into()),
+                })
+                .await
+            {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn process_command_line_args_input(&mut self) {
         debug!("begin process_command_line_args_input");
 
-        let mut parser = self.parser.command_line_args_parser();
+        let Some(mut parser) = self.parser.take_command_line_args_parser() else {
+            return;
+        };
 
         let mut line_number = 0;
 
+        if let Some(mut batcher) = Batcher::new(self.command_line_args) {
+            while let Some(batch_input) = parser.next_batch_input() {
+                line_number += 1;
+
+                let batch_entry = BatchEntry {
+                    input_line_number: InputLineNumber::new(Input::CommandLineArgs, line_number),
+                    batch_input,
+                    job_options: JobOptions::default(),
+                };
+
+                if !self
+                    .push_batch_entry(&mut batcher, batch_entry, |batch, sequence_number| {
+                        parser.parse_batch(batch, sequence_number)
+                    })
+                    .await
+                {
+                    return;
+                }
+            }
+
+            if let Some(batch) = batcher.flush() {
+                self.send_batch(batch, |batch, sequence_number| {
+                    parser.parse_batch(batch, sequence_number)
+                })
+                .await;
+            }
+
+            return;
+        }
+
+        // The number of argument groups is known up front, so the progress bar gets
+        // its full length right away.  Groups that fail to parse count as finished.
+        self.progress
+            .increment_total_commands(parser.remaining_argument_groups());
+        self.command_metrics
+            .set_total_commands(parser.remaining_argument_groups());
+
         while parser.has_remaining_argument_groups() {
             line_number += 1;
 
-            let Some(command_and_args) = parser.parse_next_argument_group() else {
+            let Some((command_and_args, argument_group)) =
+                parser.parse_next_argument_group(self.peek_sequence_number())
+            else {
+                self.progress.command_finished();
                 continue;
             };
 
-            self.send(InputMessage {
-                command_and_args,
-                input_line_number: InputLineNumber {
-                    input: Input::CommandLineArgs,
-                    line_number,
-                },
-            })
-            .await;
+            if !self
+                .send(InputMessage {
+                    command_and_args,
+                    input_line_number: InputLineNumber::new(Input::CommandLineArgs, line_number),
+                    sequence_number: self.next_sequence_number(),
+                    input_values: argument_group,
+                    job_options: JobOptions::default(),
+                    stdin: None,
+                })
+                .await
+            {
+                break;
+            }
         }
     }
 
     #[instrument(skip_all, name = "InputSenderTask::run", level = "debug")]
-    pub async fn run(self) {
+    pub async fn run(mut self) -> anyhow::Result<()> {
         debug!("begin run");
 
         match super::build_input_list(self.command_line_args) {
-            InputList::BufferedInputList(buffered_inputs) => {
+            InputList::BufferedInputs(buffered_inputs) => {
                 for buffered_input in buffered_inputs {
+                    if self.sender.is_closed() {
+                        break;
+                    }
+
                     if let Err(e) = self.process_one_buffered_input(buffered_input).await {
+                        if e.is::<InvalidJsonLineError>() {
+                            return Err(e);
+                        }
+
                         warn!(
-                            "process_one_buffered_input error buffered_input = {}: {}",
+                            "process_one_buffered_input error buffered_input = {}: {:#}",
                             buffered_input, e
                         );
                     }
                 }
             }
+            InputList::SideBySideBufferedInputs(buffered_inputs) => {
+                if let Err(e) = self
+                    .process_side_by_side_buffered_inputs(buffered_inputs)
+                    .await
+                {
+                    warn!("process_side_by_side_buffered_inputs error: {}", e);
+                }
+            }
             InputList::CommandLineArgs => self.process_command_line_args_input().await,
+            InputList::Pipe(buffered_inputs) => {
+                block_reader::check_record_separators(self.command_line_args)?;
+
+                for buffered_input in buffered_inputs {
+                    if self.sender.is_closed() {
+                        break;
+                    }
+
+                    if let Err(e) = self.process_pipe_input(buffered_input).await {
+                        warn!(
+                            "process_pipe_input error buffered_input = {}: {:#}",
+                            buffered_input, e
+                        );
+                    }
+                }
+            }
         }
 
+        // Every input has been sent, so the total number of commands is known.
+        self.command_metrics
+            .set_total_commands(self.peek_sequence_number() - 1);
+
         debug!("end run");
+
+        Ok(())
     }
 }
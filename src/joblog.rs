@@ -0,0 +1,355 @@
+use anyhow::Context;
+
+use serde::{Deserialize, Serialize};
+
+use tokio::{
+    fs::{File, OpenOptions},
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader, BufWriter},
+    sync::Mutex,
+};
+
+use tracing::{debug, warn};
+
+use std::collections::HashMap;
+
+use crate::{
+    command::CommandResult,
+    command_line_args::{CommandLineArgs, JobLogFormat},
+    common::{epoch_seconds, OwnedCommandAndArgs},
+    input::InputLineNumber,
+};
+
+/// Column names follow GNU parallel's joblog where they overlap.
+const TSV_HEADER: &str =
+    "Seq\tInput\tStarttime\tJobRuntime\tExitval\tSignal\tStdoutBytes\tStderrBytes\tCommand\n";
+
+#[derive(Serialize)]
+struct JsonJobLogEntry<'a> {
+    seq: usize,
+    input: String,
+    start_time: f64,
+    runtime: f64,
+    status: &'a str,
+    exit_code: Option<i32>,
+    signal: Option<i32>,
+    stdout_bytes: usize,
+    stderr_bytes: usize,
+    command: String,
+}
+
+/// Fields of a JSON Lines joblog entry needed to resume.
+#[derive(Deserialize)]
+struct JsonJobLogResult {
+    seq: usize,
+    status: String,
+}
+
+pub struct JobLog {
+    format: JobLogFormat,
+    writer: Mutex<BufWriter<File>>,
+}
+
+impl JobLog {
+    pub async fn new(command_line_args: &CommandLineArgs) -> anyhow::Result<Option<Self>> {
+        let Some(file_name) = &command_line_args.joblog else {
+            return Ok(None);
+        };
+
+        // When resuming, append to an existing joblog instead of replacing it.  An empty
+        // joblog still needs its header.
+        let append = command_line_args.resume_mode()
+            && tokio::fs::metadata(file_name)
+                .await
+                .is_ok_and(|metadata| metadata.len() > 0);
+
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .append(append)
+            .truncate(!append)
+            .open(file_name)
+            .await
+            .with_context(|| format!("error opening joblog file_name = '{}'", file_name))?;
+
+        let mut writer = BufWriter::new(file);
+
+        if !append && command_line_args.joblog_format == JobLogFormat::Tsv {
+            writer
+                .write_all(TSV_HEADER.as_bytes())
+                .await
+                .context("error writing joblog header")?;
+            writer
+                .flush()
+                .await
+                .context("error writing joblog header")?;
+        }
+
+        Ok(Some(Self {
+            format: command_line_args.joblog_format,
+            writer: Mutex::new(writer),
+        }))
+    }
+
+    fn format_entry(
+        &self,
+        sequence_number: usize,
+        input_line_number: &InputLineNumber,
+        command_and_args: &OwnedCommandAndArgs,
+        command_result: &CommandResult,
+    ) -> String {
+        let outcome = &command_result.outcome;
+
+        match self.format {
+            JobLogFormat::Tsv => format!(
+                "{}\t{}\t{:.3}\t{:.3}\t{}\t{}\t{}\t{}\t{}\n",
+                sequence_number,
+                input_line_number,
+                epoch_seconds(command_result.start_time),
+                command_result.runtime.as_secs_f64(),
+                outcome.exit_code().unwrap_or(-1),
+                outcome.signal().unwrap_or(0),
+                command_result.stdout_bytes,
+                command_result.stderr_bytes,
+                command_and_args.command_line().replace(['\t', '\n'], " "),
+            ),
+            JobLogFormat::Jsonl => {
+                let entry = JsonJobLogEntry {
+                    seq: sequence_number,
+                    input: input_line_number.to_string(),
+                    start_time: epoch_seconds(command_result.start_time),
+                    runtime: command_result.runtime.as_secs_f64(),
+                    status: outcome.status_name(),
+                    exit_code: outcome.exit_code(),
+                    signal: outcome.signal(),
+                    stdout_bytes: command_result.stdout_bytes,
+                    stderr_bytes: command_result.stderr_bytes,
+                    command: command_and_args.command_line(),
+                };
+
+                let mut line = serde_json::to_string(&entry).unwrap_or_default();
+                line.push('\n');
+                line
+            }
+        }
+    }
+
+    pub async fn write(
+        &self,
+        sequence_number: usize,
+        input_line_number: &InputLineNumber,
+        command_and_args: &OwnedCommandAndArgs,
+        command_result: &CommandResult,
+    ) {
+        let entry = self.format_entry(
+            sequence_number,
+            input_line_number,
+            command_and_args,
+            command_result,
+        );
+
+        let mut writer = self.writer.lock().await;
+
+        // Flushed after every entry so --resume sees all finished commands even if this
+        // run is killed.
+        if let Err(e) = writer.write_all(entry.as_bytes()).await {
+            warn!("joblog write error: {}", e);
+        } else if let Err(e) = writer.flush().await {
+            warn!("joblog flush error: {}", e);
+        }
+    }
+
+    pub async fn flush(&self) -> anyhow::Result<()> {
+        self.writer
+            .lock()
+            .await
+            .flush()
+            .await
+            .context("error flushing joblog")
+    }
+}
+
+/// Decides which inputs to skip when resuming from an existing joblog.
+pub struct ResumeFilter {
+    rerun_failed: bool,
+    /// Sequence number to whether the last logged run of that command succeeded.
+    logged_results: HashMap<usize, bool>,
+}
+
+impl ResumeFilter {
+    pub async fn new(command_line_args: &CommandLineArgs) -> anyhow::Result<Option<Self>> {
+        if !command_line_args.resume_mode() {
+            return Ok(None);
+        }
+
+        let Some(file_name) = &command_line_args.joblog else {
+            return Ok(None);
+        };
+
+        let logged_results = if tokio::fs::try_exists(file_name).await.unwrap_or(false) {
+            Self::read_joblog(file_name, command_line_args.joblog_format)
+                .await
+                .with_context(|| format!("error reading joblog file_name = '{}'", file_name))?
+        } else {
+            HashMap::new()
+        };
+
+        debug!(
+            "ResumeFilter read {} joblog entries from '{}'",
+            logged_results.len(),
+            file_name
+        );
+
+        Ok(Some(Self {
+            rerun_failed: command_line_args.resume_failed,
+            logged_results,
+        }))
+    }
+
+    async fn read_joblog(
+        file_name: &str,
+        format: JobLogFormat,
+    ) -> anyhow::Result<HashMap<usize, bool>> {
+        let file = File::open(file_name).await?;
+
+        let mut lines = BufReader::new(file).lines();
+
+        let mut logged_results = HashMap::new();
+
+        let mut tsv_columns = None;
+
+        while let Some(line) = lines.next_line().await? {
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let (sequence_number, succeeded) = match format {
+                JobLogFormat::Tsv => match &tsv_columns {
+                    None => {
+                        tsv_columns = Some(TsvColumns::from_header(&line)?);
+                        continue;
+                    }
+                    Some(tsv_columns) => tsv_columns.parse_row(&line)?,
+                },
+                JobLogFormat::Jsonl => {
+                    let entry: JsonJobLogResult = serde_json::from_str(&line)
+                        .with_context(|| format!("invalid joblog line: {}", line))?;
+                    (entry.seq, entry.status == "success")
+                }
+            };
+
+            logged_results.insert(sequence_number, succeeded);
+        }
+
+        Ok(logged_results)
+    }
+
+    pub fn skip(&self, sequence_number: usize) -> bool {
+        match self.logged_results.get(&sequence_number) {
+            None => false,
+            Some(succeeded) => !self.rerun_failed || *succeeded,
+        }
+    }
+}
+
+struct TsvColumns {
+    seq: usize,
+    exitval: usize,
+    signal: usize,
+}
+
+impl TsvColumns {
+    fn from_header(header: &str) -> anyhow::Result<Self> {
+        let columns: Vec<&str> = header.split('\t').collect();
+
+        let find = |name: &str| {
+            columns
+                .iter()
+                .position(|column| *column == name)
+                .with_context(|| format!("joblog header missing column '{}'", name))
+        };
+
+        Ok(Self {
+            seq: find("Seq")?,
+            exitval: find("Exitval")?,
+            signal: find("Signal")?,
+        })
+    }
+
+    fn parse_row(&self, line: &str) -> anyhow::Result<(usize, bool)> {
+        let fields: Vec<&str> = line.split('\t').collect();
+
+        let field = |index: usize| {
+            fields
+                .get(index)
+                .copied()
+                .with_context(|| format!("invalid joblog line: {}", line))
+        };
+
+        let sequence_number = field(self.seq)?
+            .parse()
+            .with_context(|| format!("invalid joblog Seq: {}", line))?;
+
+        let succeeded = field(self.exitval)? == "0" && field(self.signal)? == "0";
+
+        Ok((sequence_number, succeeded))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_tsv_columns() {
+        let tsv_columns = TsvColumns::from_header(TSV_HEADER.trim_end()).unwrap();
+
+        assert_eq!(
+            tsv_columns
+                .parse_row("3\tstdin:3\t1.000\t0.5\t0\t0\t1\t0\techo a")
+                .unwrap(),
+            (3, true)
+        );
+        assert_eq!(
+            tsv_columns
+                .parse_row("4\tstdin:4\t1.000\t0.5\t1\t0\t1\t0\tfalse")
+                .unwrap(),
+            (4, false)
+        );
+        assert!(tsv_columns.parse_row("x\tstdin:4").is_err());
+
+        let gnu_parallel_columns = TsvColumns::from_header(
+            "Seq\tHost\tStarttime\tJobRuntime\tSend\tReceive\tExitval\tSignal\tCommand",
+        )
+        .unwrap();
+
+        assert_eq!(
+            gnu_parallel_columns
+                .parse_row("7\t:\t1.000\t0.5\t0\t0\t0\t15\tsleep 10")
+                .unwrap(),
+            (7, false)
+        );
+    }
+
+    #[test]
+    fn test_resume_filter_skip() {
+        let logged_results = HashMap::from([(1, true), (2, false)]);
+
+        let resume_filter = ResumeFilter {
+            rerun_failed: false,
+            logged_results: logged_results.clone(),
+        };
+
+        assert!(resume_filter.skip(1));
+        assert!(resume_filter.skip(2));
+        assert!(!resume_filter.skip(3));
+
+        let resume_filter = ResumeFilter {
+            rerun_failed: true,
+            logged_results,
+        };
+
+        assert!(resume_filter.skip(1));
+        assert!(!resume_filter.skip(2));
+        assert!(!resume_filter.skip(3));
+    }
+}
@@ -1,39 +1,79 @@
-use tracing::{debug, error, instrument};
+use tracing::{debug, error, instrument, warn};
 
-use crate::command_line_args::CommandLineArgs;
+use std::io::IsTerminal;
+
+use crate::{
+    command::RunSummary,
+    command_line_args::{CommandLineArgs, OutputFormat},
+};
 
 mod command;
 mod command_line_args;
 mod common;
 mod input;
+mod joblog;
 mod output;
 mod parser;
 mod process;
 mod progress;
+mod results;
 
 #[instrument(skip_all, name = "try_main", level = "debug")]
-async fn try_main() -> anyhow::Result<()> {
+async fn try_main() -> anyhow::Result<RunSummary> {
     debug!("begin try_main");
 
     let command_line_args = CommandLineArgs::instance().await;
 
     let progress = progress::Progress::new(command_line_args)?;
 
-    let command_service = command::CommandService::new(command_line_args, progress);
+    let command_service = command::CommandService::new(command_line_args, progress).await?;
 
-    command_service.run_commands().await?;
+    let run_summary = command_service.run_commands().await?;
 
     debug!("end try_main");
 
-    Ok(())
+    Ok(run_summary)
+}
+
+/// Logs are written to stdout, except with --output-format=json where stdout holds only
+/// the JSON objects.
+fn init_tracing(command_line_args: &CommandLineArgs) {
+    if command_line_args.output_format == OutputFormat::Json {
+        tracing_subscriber::fmt()
+            .with_writer(std::io::stderr)
+            .with_ansi(std::io::stderr().is_terminal())
+            .init();
+    } else {
+        tracing_subscriber::fmt::init();
+    }
 }
 
 #[tokio::main]
 async fn main() {
-    tracing_subscriber::fmt::init();
+    init_tracing(CommandLineArgs::instance().await);
+
+    match try_main().await {
+        Err(err) => {
+            error!("fatal error in main:\n{:#}", err);
+            std::process::exit(1);
+        }
+        Ok(RunSummary {
+            command_metrics,
+            interrupt_signal,
+        }) => {
+            // Written to stderr so it doesn't mix with command output piped to another program.
+            if command_metrics.error_occurred() {
+                eprintln!("command failures: {}", command_metrics);
+            }
+
+            if let Some(signal) = interrupt_signal {
+                warn!("interrupted by {:?} signal", signal);
+                std::process::exit(process::signal_exit_code(signal));
+            }
 
-    if let Err(err) = try_main().await {
-        error!("fatal error in main:\n{:#}", err);
-        std::process::exit(1);
+            if command_metrics.error_occurred() {
+                std::process::exit(command_metrics.exit_code());
+            }
+        }
     }
 }
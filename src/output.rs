@@ -1,35 +1,176 @@
+mod buffer;
+mod tag;
 mod task;
 
+pub use self::buffer::OutputBuffer;
+
 use anyhow::Context;
 
 use tokio::{
-    sync::mpsc::{channel, Sender},
+    sync::{
+        mpsc::{channel, Sender},
+        OwnedSemaphorePermit, Semaphore,
+    },
     task::JoinHandle,
 };
 
 use tracing::{debug, warn};
 
-use std::process::Output;
+use std::{
+    ffi::OsString,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, SystemTime},
+};
+
+use crate::{
+    command::CommandResult,
+    command_line_args::{CommandLineArgs, OutputFormat},
+    common::OwnedCommandAndArgs,
+    input::InputLineNumber,
+};
+
+use self::tag::OutputTagger;
+
+#[derive(Clone, Copy, Debug)]
+pub enum OutputStream {
+    Stdout,
+    Stderr,
+}
+
+/// A finished command, written with its output as one JSON object with --output-format=json.
+#[derive(Debug)]
+pub struct JobRecord {
+    sequence_number: usize,
+    input: String,
+    command: String,
+    args: Vec<String>,
+    status: &'static str,
+    exit_code: Option<i32>,
+    signal: Option<i32>,
+    start_time: SystemTime,
+    runtime: Duration,
+}
+
+impl JobRecord {
+    pub fn new(
+        sequence_number: usize,
+        input_line_number: &InputLineNumber,
+        command_and_args: &OwnedCommandAndArgs,
+        command_result: &CommandResult,
+    ) -> Self {
+        Self {
+            sequence_number,
+            input: input_line_number.to_string(),
+            command: command_and_args.command_path.to_string_lossy().into_owned(),
+            args: command_and_args
+                .args
+                .iter()
+                .map(|arg| arg.to_string_lossy().into_owned())
+                .collect(),
+            status: command_result.outcome.status_name(),
+            exit_code: command_result.outcome.exit_code(),
+            signal: command_result.outcome.signal(),
+            start_time: command_result.start_time,
+            runtime: command_result.runtime,
+        }
+    }
+}
 
-use crate::command_line_args::CommandLineArgs;
+#[derive(Debug)]
+enum OutputContent {
+    /// Complete lines from a running command in line buffer mode.
+    Lines { stream: OutputStream, data: Vec<u8> },
+
+    /// Buffered output of a finished command.
+    Finished(Box<FinishedOutput>),
+}
+
+#[derive(Debug)]
+struct FinishedOutput {
+    stdout: OutputBuffer,
+    stderr: OutputBuffer,
+    job_record: Option<JobRecord>,
+}
+
+#[derive(Debug)]
+struct OutputMessage {
+    output_index: usize,
+    tag: Option<Arc<str>>,
+    content: OutputContent,
+    _keep_order_permit: Option<OwnedSemaphorePermit>,
+}
 
 pub struct OutputSender {
-    sender: Sender<Output>,
+    sender: Sender<OutputMessage>,
+    output_index: usize,
+    tag: Option<Arc<str>>,
+    keep_order_permit: Option<OwnedSemaphorePermit>,
+    json: bool,
 }
 
 impl OutputSender {
-    pub async fn send(self, output: Output) {
-        if output.stdout.is_empty() && output.stderr.is_empty() {
-            return;
-        }
-        if let Err(e) = self.sender.send(output).await {
+    async fn send_message(&self, output_message: OutputMessage) {
+        if let Err(e) = self.sender.send(output_message).await {
             warn!("sender.send error: {}", e);
         }
     }
+
+    /// Send complete lines written by a running command.
+    pub async fn send_lines(&self, stream: OutputStream, data: Vec<u8>) {
+        self.send_message(OutputMessage {
+            output_index: self.output_index,
+            tag: self.tag.clone(),
+            content: OutputContent::Lines { stream, data },
+            _keep_order_permit: None,
+        })
+        .await;
+    }
+
+    /// Returns true if finished commands are written as JSON objects, which need a `JobRecord`.
+    pub fn json(&self) -> bool {
+        self.json
+    }
+
+    /// Send the buffered output of a finished command, empty if the command did not complete.
+    pub async fn send(
+        mut self,
+        stdout: OutputBuffer,
+        stderr: OutputBuffer,
+        job_record: Option<JobRecord>,
+    ) {
+        // In keep order mode every command must send a message so later outputs are not held back.
+        if stdout.is_empty()
+            && stderr.is_empty()
+            && job_record.is_none()
+            && self.keep_order_permit.is_none()
+        {
+            return;
+        }
+
+        let output_message = OutputMessage {
+            output_index: self.output_index,
+            tag: self.tag.take(),
+            content: OutputContent::Finished(Box::new(FinishedOutput {
+                stdout,
+                stderr,
+                job_record,
+            })),
+            _keep_order_permit: self.keep_order_permit.take(),
+        };
+
+        self.send_message(output_message).await;
+    }
 }
 
 pub struct OutputWriter {
-    sender: Sender<Output>,
+    sender: Sender<OutputMessage>,
+    next_output_index: AtomicUsize,
+    keep_order_semaphore: Option<Arc<Semaphore>>,
+    output_tagger: OutputTagger,
+    json: bool,
     receiver_task_join_handle: JoinHandle<()>,
 }
 
@@ -41,18 +182,60 @@ impl OutputWriter {
             command_line_args.channel_capacity,
         );
 
-        let receiver_task_join_handle = tokio::spawn(task::OutputReceiverTask::new(receiver).run());
+        let keep_order_semaphore = if command_line_args.keep_order_mode() {
+            let keep_order_buffer_size = command_line_args.keep_order_buffer_size();
+            debug!(
+                "created keep order semaphore with {} permits",
+                keep_order_buffer_size
+            );
+            Some(Arc::new(Semaphore::new(keep_order_buffer_size)))
+        } else {
+            None
+        };
+
+        let receiver_task_join_handle =
+            tokio::spawn(task::OutputReceiverTask::new(receiver, command_line_args).run());
 
         Self {
             sender,
+            next_output_index: AtomicUsize::new(0),
+            keep_order_semaphore,
+            output_tagger: OutputTagger::new(command_line_args),
+            json: command_line_args.output_format == OutputFormat::Json,
             receiver_task_join_handle,
         }
     }
 
-    pub fn sender(&self) -> OutputSender {
-        OutputSender {
+    /// Create the sender for the next command.
+    ///
+    /// In keep order mode this waits until the number of commands running or waiting
+    /// to be written is below the keep order buffer size.
+    pub async fn sender(
+        &self,
+        input_values: &[OsString],
+        sequence_number: usize,
+        input_line_number: &InputLineNumber,
+        job_slot: Option<usize>,
+    ) -> anyhow::Result<OutputSender> {
+        let keep_order_permit = match &self.keep_order_semaphore {
+            None => None,
+            Some(keep_order_semaphore) => Some(
+                Arc::clone(keep_order_semaphore)
+                    .acquire_owned()
+                    .await
+                    .context("keep_order_semaphore.acquire_owned error")?,
+            ),
+        };
+
+        Ok(OutputSender {
             sender: self.sender.clone(),
-        }
+            output_index: self.next_output_index.fetch_add(1, Ordering::SeqCst),
+            tag: self
+                .output_tagger
+                .tag(input_values, sequence_number, input_line_number, job_slot),
+            keep_order_permit,
+            json: self.json,
+        })
     }
 
     pub async fn wait_for_completion(self) -> anyhow::Result<()> {
@@ -0,0 +1,127 @@
+use tokio::{
+    fs::File,
+    io::{
+        AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncSeekExt, AsyncWrite, AsyncWriteExt,
+        BufReader, BufWriter,
+    },
+};
+
+use tracing::debug;
+
+use std::io::SeekFrom;
+
+const IO_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Output of one stream of a finished command.  Output larger than the memory limit
+/// is written to an anonymous temporary file, removed when the buffer is dropped.
+#[derive(Debug)]
+pub enum OutputBuffer {
+    Memory(Vec<u8>),
+
+    File { file: File, len: usize },
+}
+
+impl Default for OutputBuffer {
+    fn default() -> Self {
+        Self::Memory(vec![])
+    }
+}
+
+impl OutputBuffer {
+    /// Reads `reader` to the end, spilling to a temporary file past `memory_limit` bytes.
+    pub async fn read_from(
+        reader: Option<impl AsyncRead + Unpin>,
+        memory_limit: usize,
+    ) -> std::io::Result<Self> {
+        let Some(mut reader) = reader else {
+            return Ok(Self::default());
+        };
+
+        let mut buffer = vec![];
+
+        // Read one byte past the limit to tell whether output exceeds it.
+        (&mut reader)
+            .take(memory_limit as u64 + 1)
+            .read_to_end(&mut buffer)
+            .await?;
+
+        if buffer.len() <= memory_limit {
+            return Ok(Self::Memory(buffer));
+        }
+
+        let std_file = tokio::task::spawn_blocking(tempfile::tempfile).await??;
+        let mut file = File::from_std(std_file);
+
+        file.write_all(&buffer).await?;
+        let copied = tokio::io::copy(&mut reader, &mut file).await?;
+        file.flush().await?;
+
+        let len = buffer.len() + copied as usize;
+
+        debug!("spilled {} bytes of output to a temporary file", len);
+
+        Ok(Self::File { file, len })
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            Self::Memory(buffer) => buffer.len(),
+            Self::File { len, .. } => *len,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Reader over the whole output from the beginning.
+    async fn reader(&self) -> std::io::Result<Box<dyn AsyncRead + Unpin + Send + '_>> {
+        match self {
+            Self::Memory(buffer) => Ok(Box::new(&buffer[..])),
+            Self::File { file, .. } => {
+                // The clone shares the file offset, which is only used here.
+                let mut file = file.try_clone().await?;
+                file.seek(SeekFrom::Start(0)).await?;
+                Ok(Box::new(file))
+            }
+        }
+    }
+
+    /// Returns the whole output in memory.
+    pub async fn into_vec(self) -> std::io::Result<Vec<u8>> {
+        match self {
+            Self::Memory(buffer) => Ok(buffer),
+            buffer => {
+                let mut data = Vec::with_capacity(buffer.len());
+                buffer.reader().await?.read_to_end(&mut data).await?;
+                Ok(data)
+            }
+        }
+    }
+
+    /// Copies the whole output to `writer`.
+    pub async fn copy_to(&self, writer: &mut (impl AsyncWrite + Unpin)) -> std::io::Result<u64> {
+        let mut reader = self.reader().await?;
+
+        tokio::io::copy(&mut reader, writer).await
+    }
+
+    /// Copies the whole output to `writer` line by line, with `prefix` before each line.
+    pub async fn copy_prefixed_to(
+        &self,
+        prefix: &str,
+        writer: &mut (impl AsyncWrite + Unpin),
+    ) -> std::io::Result<()> {
+        let mut reader = BufReader::with_capacity(IO_BUFFER_SIZE, self.reader().await?);
+        let mut writer = BufWriter::with_capacity(IO_BUFFER_SIZE, writer);
+        let mut line = vec![];
+
+        while reader.read_until(b'\n', &mut line).await? > 0 {
+            writer.write_all(prefix.as_bytes()).await?;
+            writer.write_all(&line).await?;
+            line.clear();
+        }
+
+        writer.flush().await
+    }
+}
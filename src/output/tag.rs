@@ -0,0 +1,188 @@
+use std::{
+    ffi::{OsStr, OsString},
+    sync::Arc,
+};
+
+use crate::{
+    command_line_args::CommandLineArgs,
+    common::{os_string_from_bytes, JOB_SLOT},
+    input::InputLineNumber,
+    parser,
+};
+
+const DEFAULT_TAG_TEMPLATE: &str = "{}";
+
+const LINE_NUMBER: &str = "{line}";
+
+pub struct OutputTagger {
+    /// The tag template split at each {line}.
+    template_parts: Option<Vec<String>>,
+}
+
+impl OutputTagger {
+    pub fn new(command_line_args: &CommandLineArgs) -> Self {
+        let template = match &command_line_args.tagstring {
+            Some(tagstring) => Some(tagstring.as_str()),
+            None if command_line_args.tag => Some(DEFAULT_TAG_TEMPLATE),
+            None => None,
+        };
+
+        Self {
+            template_parts: template
+                .map(|template| template.split(LINE_NUMBER).map(str::to_owned).collect()),
+        }
+    }
+
+    /// Expand the tag template for one command.
+    ///
+    /// The template has the same replacement strings as commands, e.g. `{}` for the
+    /// input values joined by spaces, `{1}` for one of them, `{#}` for the sequence
+    /// number and `{%}` for `job_slot`, plus `{line}` for the input name and line number.
+    pub fn tag(
+        &self,
+        input_values: &[OsString],
+        sequence_number: usize,
+        input_line_number: &InputLineNumber,
+        job_slot: Option<usize>,
+    ) -> Option<Arc<str>> {
+        let template_parts = self.template_parts.as_ref()?;
+
+        let line_number = input_line_number.to_string();
+
+        let mut result = Vec::new();
+
+        let mut job_slot_offsets = vec![];
+
+        for (i, part) in template_parts.iter().enumerate() {
+            if i > 0 {
+                result.extend_from_slice(line_number.as_bytes());
+            }
+
+            let mut part_job_slot_offsets = vec![];
+
+            let replaced = parser::replace_tag_template(
+                OsStr::new(part),
+                input_values,
+                sequence_number,
+                &mut part_job_slot_offsets,
+            );
+
+            job_slot_offsets.extend(
+                part_job_slot_offsets
+                    .into_iter()
+                    .map(|offset| result.len() + offset),
+            );
+
+            result.extend_from_slice(replaced.as_encoded_bytes());
+        }
+
+        if let Some(job_slot) = job_slot {
+            result = replace_job_slots(&result, &job_slot_offsets, job_slot);
+        }
+
+        Some(os_string_from_bytes(result).to_string_lossy().into())
+    }
+}
+
+/// Replaces the {%} at each of `offsets`, in increasing order, with `job_slot`.
+fn replace_job_slots(bytes: &[u8], offsets: &[usize], job_slot: usize) -> Vec<u8> {
+    let job_slot = job_slot.to_string();
+
+    let mut result = Vec::with_capacity(bytes.len());
+
+    let mut start = 0;
+
+    for &offset in offsets {
+        result.extend_from_slice(&bytes[start..offset]);
+        result.extend_from_slice(job_slot.as_bytes());
+        start = offset + JOB_SLOT.len();
+    }
+
+    result.extend_from_slice(&bytes[start..]);
+
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::input::{BufferedInput, Input};
+
+    fn input_line_number() -> InputLineNumber {
+        InputLineNumber::new(Input::Buffered(BufferedInput::Stdin), 3)
+    }
+
+    #[test]
+    fn test_tag_disabled() {
+        let command_line_args = CommandLineArgs::default();
+
+        let tagger = OutputTagger::new(&command_line_args);
+
+        assert_eq!(
+            tagger.tag(&[OsString::from("A")], 1, &input_line_number(), None),
+            None
+        );
+    }
+
+    #[test]
+    fn test_tag() {
+        let command_line_args = CommandLineArgs {
+            tag: true,
+            ..Default::default()
+        };
+
+        let tagger = OutputTagger::new(&command_line_args);
+
+        assert_eq!(
+            tagger
+                .tag(
+                    &[OsString::from("A"), OsString::from("B")],
+                    1,
+                    &input_line_number(),
+                    None
+                )
+                .as_deref(),
+            Some("A B")
+        );
+    }
+
+    #[test]
+    fn test_tagstring() {
+        let command_line_args = CommandLineArgs {
+            tagstring: Some("[{#} {line}] {} {unknown}".to_owned()),
+            ..Default::default()
+        };
+
+        let tagger = OutputTagger::new(&command_line_args);
+
+        assert_eq!(
+            tagger
+                .tag(&[OsString::from("{#}")], 7, &input_line_number(), None)
+                .as_deref(),
+            Some("[7 stdin:3] {#} {unknown}")
+        );
+    }
+
+    #[test]
+    fn test_tagstring_replacement_strings() {
+        let command_line_args = CommandLineArgs {
+            tagstring: Some("{1}:{2/.}:{%}:{line}:{%}".to_owned()),
+            ..Default::default()
+        };
+
+        let tagger = OutputTagger::new(&command_line_args);
+
+        assert_eq!(
+            tagger
+                .tag(
+                    &[OsString::from("{%}"), OsString::from("dir/b.txt")],
+                    1,
+                    &input_line_number(),
+                    Some(12)
+                )
+                .as_deref(),
+            Some("{%}:b:12:stdin:3:12")
+        );
+    }
+}
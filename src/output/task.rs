@@ -1,41 +1,257 @@
 use tokio::{io::AsyncWrite, sync::mpsc::Receiver};
 
-use tracing::{debug, instrument, trace};
+use base64::{prelude::BASE64_STANDARD, Engine};
 
-use std::process::Output;
+use serde::Serialize;
+
+use tracing::{debug, instrument, trace, warn};
+
+use std::{borrow::Cow, collections::BTreeMap, time::SystemTime};
+
+use crate::{command_line_args::CommandLineArgs, common::epoch_seconds};
+
+use super::{FinishedOutput, JobRecord, OutputBuffer, OutputContent, OutputMessage, OutputStream};
 
 pub struct OutputReceiverTask {
-    receiver: Receiver<Output>,
+    receiver: Receiver<OutputMessage>,
+    keep_order: bool,
+    timestamp: bool,
 }
 
 impl OutputReceiverTask {
-    pub fn new(receiver: Receiver<Output>) -> Self {
-        Self { receiver }
+    pub fn new(receiver: Receiver<OutputMessage>, command_line_args: &CommandLineArgs) -> Self {
+        Self {
+            receiver,
+            keep_order: command_line_args.keep_order_mode(),
+            timestamp: command_line_args.timestamp,
+        }
     }
 
     #[instrument(skip_all, name = "OutputReceiverTask::run", level = "debug")]
     pub async fn run(self) {
         debug!("begin run");
 
+        let mut writer = OutputMessageWriter {
+            stdout: tokio::io::stdout(),
+            stderr: tokio::io::stderr(),
+            timestamp: self.timestamp,
+        };
+
+        let mut receiver = self.receiver;
+
+        if !self.keep_order {
+            while let Some(output_message) = receiver.recv().await {
+                writer.write(output_message).await;
+            }
+        } else {
+            let mut next_output_index = 0;
+            let mut reorder_buffer = BTreeMap::new();
+
+            while let Some(output_message) = receiver.recv().await {
+                if matches!(output_message.content, OutputContent::Lines { .. }) {
+                    writer.write(output_message).await;
+                    continue;
+                }
+
+                reorder_buffer.insert(output_message.output_index, output_message);
+
+                while let Some(output_message) = reorder_buffer.remove(&next_output_index) {
+                    writer.write(output_message).await;
+                    next_output_index += 1;
+                }
+
+                trace!("reorder_buffer.len() = {}", reorder_buffer.len());
+            }
+
+            // Commands that never sent output (e.g. after a halt) leave gaps, write whatever is left in order.
+            for output_message in reorder_buffer.into_values() {
+                writer.write(output_message).await;
+            }
+        }
+
+        debug!("end run");
+    }
+}
+
+struct OutputMessageWriter<O, E> {
+    stdout: O,
+    stderr: E,
+    timestamp: bool,
+}
+
+impl<O, E> OutputMessageWriter<O, E>
+where
+    O: AsyncWrite + Unpin,
+    E: AsyncWrite + Unpin,
+{
+    async fn write(&mut self, output_message: OutputMessage) {
         async fn copy(mut buffer: &[u8], output_stream: &mut (impl AsyncWrite + Unpin)) {
             let result = tokio::io::copy(&mut buffer, &mut *output_stream).await;
             trace!("copy result = {:?}", result);
         }
 
-        let mut stdout = tokio::io::stdout();
-        let mut stderr = tokio::io::stderr();
+        let prefix = self.line_prefix(output_message.tag.as_deref());
 
-        let mut receiver = self.receiver;
+        let prefixed = |data: Vec<u8>| match &prefix {
+            None => data,
+            Some(prefix) => prefix_lines(prefix, &data),
+        };
 
-        while let Some(command_output) = receiver.recv().await {
-            if !command_output.stdout.is_empty() {
-                copy(&command_output.stdout, &mut stdout).await;
-            }
-            if !command_output.stderr.is_empty() {
-                copy(&command_output.stderr, &mut stderr).await;
+        match output_message.content {
+            OutputContent::Lines { stream, data } => match stream {
+                OutputStream::Stdout => copy(&prefixed(data), &mut self.stdout).await,
+                OutputStream::Stderr => copy(&prefixed(data), &mut self.stderr).await,
+            },
+            OutputContent::Finished(finished_output) => {
+                let FinishedOutput {
+                    stdout,
+                    stderr,
+                    job_record,
+                } = *finished_output;
+
+                match job_record {
+                    Some(job_record) => {
+                        let line = json_line(job_record, stdout, stderr).await;
+                        copy(line.as_bytes(), &mut self.stdout).await;
+                    }
+                    None => {
+                        copy_buffer(stdout, prefix.as_deref(), &mut self.stdout).await;
+                        copy_buffer(stderr, prefix.as_deref(), &mut self.stderr).await;
+                    }
+                }
             }
         }
+    }
 
-        debug!("end run");
+    fn line_prefix(&self, tag: Option<&str>) -> Option<String> {
+        let timestamp = self
+            .timestamp
+            .then(|| humantime::format_rfc3339_millis(SystemTime::now()).to_string());
+
+        let prefix = [timestamp.map(Cow::from), tag.map(Cow::from)]
+            .into_iter()
+            .flatten()
+            .fold(String::new(), |mut prefix, part| {
+                prefix.push_str(&part);
+                prefix.push('\t');
+                prefix
+            });
+
+        (!prefix.is_empty()).then_some(prefix)
+    }
+}
+
+#[derive(Serialize)]
+struct JsonJobOutput<'a> {
+    seq: usize,
+    input: &'a str,
+    command: &'a str,
+    args: &'a [String],
+    status: &'a str,
+    exit_code: Option<i32>,
+    signal: Option<i32>,
+    start_time: f64,
+    runtime: f64,
+    stdout: String,
+    stdout_encoding: &'a str,
+    stderr: String,
+    stderr_encoding: &'a str,
+}
+
+/// Output as a UTF-8 string, or base64 if it isn't valid UTF-8, with the name of its encoding.
+async fn encode_output(buffer: OutputBuffer) -> (String, &'static str) {
+    let data = match buffer.into_vec().await {
+        Ok(data) => data,
+        Err(e) => {
+            warn!("output read error: {}", e);
+            vec![]
+        }
+    };
+
+    match String::from_utf8(data) {
+        Ok(output) => (output, "utf8"),
+        Err(e) => (BASE64_STANDARD.encode(e.as_bytes()), "base64"),
+    }
+}
+
+/// One line of --output-format=json for a finished command.
+async fn json_line(job_record: JobRecord, stdout: OutputBuffer, stderr: OutputBuffer) -> String {
+    let (stdout, stdout_encoding) = encode_output(stdout).await;
+    let (stderr, stderr_encoding) = encode_output(stderr).await;
+
+    let json_job_output = JsonJobOutput {
+        seq: job_record.sequence_number,
+        input: &job_record.input,
+        command: &job_record.command,
+        args: &job_record.args,
+        status: job_record.status,
+        exit_code: job_record.exit_code,
+        signal: job_record.signal,
+        start_time: epoch_seconds(job_record.start_time),
+        runtime: job_record.runtime.as_secs_f64(),
+        stdout,
+        stdout_encoding,
+        stderr,
+        stderr_encoding,
+    };
+
+    let mut line = serde_json::to_string(&json_job_output).unwrap_or_default();
+    line.push('\n');
+    line
+}
+
+/// Output spilled to a file is streamed to `output_stream` instead of being read back into memory.
+async fn copy_buffer(
+    buffer: OutputBuffer,
+    prefix: Option<&str>,
+    output_stream: &mut (impl AsyncWrite + Unpin),
+) {
+    if buffer.is_empty() {
+        return;
+    }
+
+    let result = match buffer {
+        OutputBuffer::Memory(data) => {
+            let data = match prefix {
+                None => data,
+                Some(prefix) => prefix_lines(prefix, &data),
+            };
+            tokio::io::copy(&mut &data[..], output_stream)
+                .await
+                .map(drop)
+        }
+        buffer => match prefix {
+            None => buffer.copy_to(output_stream).await.map(drop),
+            Some(prefix) => buffer.copy_prefixed_to(prefix, output_stream).await,
+        },
+    };
+
+    trace!("copy result = {:?}", result);
+}
+
+fn prefix_lines(prefix: &str, data: &[u8]) -> Vec<u8> {
+    let line_count = data.split_inclusive(|b| *b == b'\n').count();
+
+    let mut result = Vec::with_capacity(data.len() + (line_count * prefix.len()));
+
+    for line in data.split_inclusive(|b| *b == b'\n') {
+        result.extend_from_slice(prefix.as_bytes());
+        result.extend_from_slice(line);
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_prefix_lines() {
+        assert_eq!(prefix_lines("tag\t", b"a\nb\n"), b"tag\ta\ntag\tb\n");
+
+        assert_eq!(prefix_lines("tag\t", b"a\nb"), b"tag\ta\ntag\tb");
+
+        assert_eq!(prefix_lines("tag\t", b""), b"");
     }
 }
@@ -1,13 +1,23 @@
 pub mod buffered;
+mod colsep;
 pub mod command_line;
+mod csv_input;
+mod json_input;
 mod regex;
+mod replace;
 
 use tokio::sync::OnceCell;
 
-use crate::{command_line_args::CommandLineArgs, common::OwnedCommandAndArgs};
+use std::ffi::{OsStr, OsString};
+
+use crate::{
+    command_line_args::{ArgumentGroupSeparator, CommandLineArgs},
+    common::{join_os_strings, JobSlotPosition, OwnedCommandAndArgs},
+};
 
 use self::{
-    buffered::BufferedInputLineParser, command_line::CommandLineArgsParser, regex::RegexProcessor,
+    buffered::BufferedInputLineParser, colsep::ColumnSeparator,
+    command_line::CommandLineArgsParser, regex::RegexProcessor,
 };
 
 struct ShellCommandAndArgs(Option<Vec<String>>);
@@ -33,35 +43,342 @@ impl ShellCommandAndArgs {
     }
 }
 
+pub use self::{csv_input::CsvHeader, json_input::InvalidJsonError};
+
+/// The command and initial arguments before any ::: separator, which inputs are appended
+/// to or substituted into.
+fn command_template(command_line_args: &CommandLineArgs) -> &[OsString] {
+    let args = &command_line_args.command_and_initial_arguments;
+
+    let end = args
+        .iter()
+        .position(|arg| ArgumentGroupSeparator::parse(arg).is_some())
+        .unwrap_or(args.len());
+
+    &args[..end]
+}
+
+/// Returns true if the replacement string engine is used instead of appending inputs.
+fn replace_mode(command_line_args: &CommandLineArgs) -> bool {
+    let command_template = command_template(command_line_args);
+
+    command_line_args.regex.is_none()
+        && (replace::contains_replacement_strings(command_template)
+            || (command_line_args.jsonl && replace::contains_json_paths(command_template)))
+}
+
+/// Returns true if commands refer to input values by position, e.g. {1} or {2/}.
+pub fn command_uses_positional_replacement_strings(command_line_args: &CommandLineArgs) -> bool {
+    replace_mode(command_line_args)
+        && replace::contains_positional_replacement_strings(command_template(command_line_args))
+}
+
+/// Command and arguments before the command is built, with the positions of the {%}
+/// left from the command template.
+#[derive(Debug, Default)]
+struct CommandArgs {
+    args: Vec<OsString>,
+    job_slots: Vec<JobSlotPosition>,
+}
+
+impl CommandArgs {
+    fn push_replaced<V: AsRef<OsStr>>(
+        &mut self,
+        arg: &OsStr,
+        replacement_values: &replace::ReplacementValues<V>,
+    ) {
+        let mut job_slot_offsets = vec![];
+
+        let replaced = replace::replace(arg, replacement_values, &mut job_slot_offsets);
+
+        self.job_slots
+            .extend(job_slot_offsets.into_iter().map(|offset| JobSlotPosition {
+                arg: self.args.len(),
+                offset,
+            }));
+        self.args.push(replaced.into());
+    }
+}
+
+impl FromIterator<OsString> for CommandArgs {
+    fn from_iter<I: IntoIterator<Item = OsString>>(iter: I) -> Self {
+        Self {
+            args: iter.into_iter().collect(),
+            job_slots: vec![],
+        }
+    }
+}
+
+/// Substitutes `input_values` into the replacement strings of `command_template`.
+/// `column_names` name the input values for {name} and {col:name}, and may be empty.
+fn replace_command_template<V: AsRef<OsStr>>(
+    command_template: &[OsString],
+    input_values: &[V],
+    column_names: &[String],
+    sequence_number: usize,
+) -> CommandArgs {
+    let input = join_os_strings(input_values);
+
+    replace_command_template_values(
+        command_template,
+        &replace::ReplacementValues {
+            input: &input,
+            input_values,
+            column_names,
+            json: None,
+            sequence_number,
+        },
+    )
+}
+
+fn replace_command_template_values<V: AsRef<OsStr>>(
+    command_template: &[OsString],
+    replacement_values: &replace::ReplacementValues<V>,
+) -> CommandArgs {
+    let mut result = CommandArgs::default();
+
+    for arg in command_template {
+        result.push_replaced(arg, replacement_values);
+    }
+
+    result
+}
+
+/// Values of one input of a batch, substituted into the command template when the batch
+/// is complete.
+#[derive(Debug)]
+pub struct BatchInput {
+    input_values: Vec<OsString>,
+    json: Option<serde_json::Value>,
+}
+
+impl BatchInput {
+    pub fn new(input_values: Vec<OsString>) -> Self {
+        Self {
+            input_values,
+            json: None,
+        }
+    }
+
+    pub fn input_values(&self) -> &[OsString] {
+        &self.input_values
+    }
+
+    pub fn into_input_values(self) -> Vec<OsString> {
+        self.input_values
+    }
+}
+
+/// Builds one command line from a batch of inputs.  Arguments that refer to an input
+/// are repeated once per input, other arguments appear once.
+fn replace_batch_command_template(
+    command_template: &[OsString],
+    batch: &[BatchInput],
+    column_names: &[String],
+    json_paths: bool,
+    sequence_number: usize,
+) -> CommandArgs {
+    let inputs: Vec<OsString> = batch
+        .iter()
+        .map(|batch_input| join_os_strings(&batch_input.input_values))
+        .collect();
+
+    let all_replacement_values: Vec<replace::ReplacementValues<OsString>> = batch
+        .iter()
+        .zip(&inputs)
+        .map(|(batch_input, input)| replace::ReplacementValues {
+            input,
+            input_values: &batch_input.input_values,
+            column_names,
+            json: batch_input.json.as_ref(),
+            sequence_number,
+        })
+        .collect();
+
+    let mut result = CommandArgs::default();
+
+    for arg in command_template {
+        if replace::refers_to_input(arg, column_names, json_paths) {
+            for replacement_values in &all_replacement_values {
+                result.push_replaced(arg, replacement_values);
+            }
+        } else if let Some(replacement_values) = all_replacement_values.first() {
+            result.push_replaced(arg, replacement_values);
+        }
+    }
+
+    result
+}
+
+/// Estimates the command line length of batches for -X.
+pub struct BatchLengthEstimator {
+    template_bytes: usize,
+    repeated_template_bytes: usize,
+    repeat_count: usize,
+    /// Bytes counted per argument besides its value.
+    arg_overhead_bytes: usize,
+}
+
+impl BatchLengthEstimator {
+    pub fn new(command_line_args: &CommandLineArgs) -> Self {
+        let command_template = command_template(command_line_args);
+
+        // A separator or terminating nul, and without --shell a pointer in the argument
+        // vector, which also counts against the system limit.
+        let arg_overhead_bytes = if command_line_args.shell {
+            1
+        } else {
+            1 + std::mem::size_of::<usize>()
+        };
+
+        let arg_bytes = |arg: &OsString| arg.len() + arg_overhead_bytes;
+
+        // Overestimates by counting every argument with braces as repeated per input.
+        let repeated_args: Vec<&OsString> = command_template
+            .iter()
+            .filter(|arg| arg.as_encoded_bytes().contains(&b'{'))
+            .collect();
+
+        Self {
+            template_bytes: command_template.iter().map(arg_bytes).sum(),
+            repeated_template_bytes: repeated_args.iter().copied().map(arg_bytes).sum(),
+            repeat_count: repeated_args.len().max(1),
+            arg_overhead_bytes,
+        }
+    }
+
+    /// Bytes of the command line without inputs.
+    pub fn template_bytes(&self) -> usize {
+        self.template_bytes
+    }
+
+    /// Bytes one input adds to the command line.
+    pub fn input_bytes(&self, input_values: &[OsString]) -> usize {
+        let values_bytes: usize = input_values
+            .iter()
+            .map(|value| value.len() + self.arg_overhead_bytes)
+            .sum();
+
+        self.repeat_count * values_bytes + self.repeated_template_bytes
+    }
+}
+
+/// Returns true if commands or the --tagstring contain {%} that must be replaced with the
+/// job slot when started.
+pub fn uses_job_slot(command_line_args: &CommandLineArgs) -> bool {
+    let command_uses_job_slot = replace_mode(command_line_args)
+        && replace::contains_job_slot(command_template(command_line_args));
+
+    command_uses_job_slot
+        || command_line_args
+            .tagstring
+            .as_ref()
+            .is_some_and(|tagstring| replace::contains_job_slot(&[tagstring]))
+}
+
+/// Substitutes `input_values` into the replacement strings of a --tagstring template,
+/// which are the same as those of commands.  The byte offsets of the {%} left in the
+/// result are pushed to `job_slot_offsets`.
+pub fn replace_tag_template(
+    template: &OsStr,
+    input_values: &[OsString],
+    sequence_number: usize,
+    job_slot_offsets: &mut Vec<usize>,
+) -> OsString {
+    let input = join_os_strings(input_values);
+
+    let replacement_values = replace::ReplacementValues {
+        input: &input,
+        input_values,
+        column_names: &[],
+        json: None,
+        sequence_number,
+    };
+
+    replace::replace(template, &replacement_values, job_slot_offsets).into_owned()
+}
+
 fn build_owned_command_and_args(
     shell_command_and_args: &ShellCommandAndArgs,
-    command_and_args: Vec<String>,
+    command_and_args: CommandArgs,
 ) -> Option<OwnedCommandAndArgs> {
+    let CommandArgs { args, job_slots } = command_and_args;
+
     match &shell_command_and_args.0 {
-        None => OwnedCommandAndArgs::try_from(command_and_args).ok(),
+        None => {
+            let mut result = OwnedCommandAndArgs::try_from(args).ok()?;
+
+            // The command path isn't an argument, {%} in it is left as is.
+            result.job_slots = job_slots
+                .into_iter()
+                .filter(|position| position.arg > 0)
+                .map(|position| JobSlotPosition {
+                    arg: position.arg - 1,
+                    ..position
+                })
+                .collect();
+
+            Some(result)
+        }
         Some(shell_command_and_args) => {
+            // Offsets of each argument in the joined shell command.
+            let arg_offsets: Vec<usize> = args
+                .iter()
+                .scan(0, |offset, arg| {
+                    let arg_offset = *offset;
+                    *offset += arg.len() + 1;
+                    Some(arg_offset)
+                })
+                .collect();
+
             let mut result = Vec::with_capacity(shell_command_and_args.len() + 1);
 
-            result.extend(shell_command_and_args.iter().cloned());
-            result.push(command_and_args.join(" "));
+            result.extend(shell_command_and_args.iter().map(OsString::from));
+            result.push(join_os_strings(&args));
+
+            let mut result = OwnedCommandAndArgs::try_from(result).ok()?;
+
+            let shell_command_arg = result.args.len() - 1;
+
+            result.job_slots = job_slots
+                .into_iter()
+                .map(|position| JobSlotPosition {
+                    arg: shell_command_arg,
+                    offset: arg_offsets[position.arg] + position.offset,
+                })
+                .collect();
 
-            OwnedCommandAndArgs::try_from(result).ok()
+            Some(result)
         }
     }
 }
 
 pub struct Parser {
     buffered_input_line_parser: OnceCell<BufferedInputLineParser>,
+    command_line_args_parser: Option<CommandLineArgsParser>,
     regex_processor: RegexProcessor,
+    column_separator: ColumnSeparator,
     command_line_args: &'static CommandLineArgs,
 }
 
 impl Parser {
-    pub fn new(command_line_args: &'static CommandLineArgs) -> anyhow::Result<Self> {
+    pub async fn new(command_line_args: &'static CommandLineArgs) -> anyhow::Result<Self> {
         let regex_processor = RegexProcessor::new(command_line_args)?;
+
+        let column_separator = ColumnSeparator::new(command_line_args)?;
+
+        // Built up front so invalid argument groups are reported before any command runs.
+        let command_line_args_parser = if command_line_args.commands_from_args_mode() {
+            Some(CommandLineArgsParser::new(command_line_args, regex_processor.clone()).await?)
+        } else {
+            None
+        };
+
         Ok(Self {
             buffered_input_line_parser: OnceCell::new(),
+            command_line_args_parser,
             regex_processor,
+            column_separator,
             command_line_args,
         })
     }
@@ -69,12 +386,17 @@ impl Parser {
     pub async fn buffered_input_line_parser(&self) -> &BufferedInputLineParser {
         self.buffered_input_line_parser
             .get_or_init(|| async move {
-                BufferedInputLineParser::new(self.command_line_args, self.regex_processor.clone())
+                BufferedInputLineParser::new(
+                    self.command_line_args,
+                    self.regex_processor.clone(),
+                    self.column_separator.clone(),
+                )
             })
             .await
     }
 
-    pub fn command_line_args_parser(&self) -> CommandLineArgsParser {
-        CommandLineArgsParser::new(self.command_line_args, self.regex_processor.clone())
+    /// Returns None if not in commands from args mode or if already taken.
+    pub fn take_command_line_args_parser(&mut self) -> Option<CommandLineArgsParser> {
+        self.command_line_args_parser.take()
     }
 }
@@ -1,21 +1,46 @@
+use anyhow::Context;
+
 use itertools::Itertools;
-use shlex::split;
+
+use std::ffi::{OsStr, OsString};
 
 use crate::{
     command_line_args::CommandLineArgs,
-    common::OwnedCommandAndArgs,
-    parser::{regex::RegexProcessor, ShellCommandAndArgs},
+    common::{os_string_from_bytes, os_string_from_input, JobOptions, OwnedCommandAndArgs},
+    parser::{
+        colsep::ColumnSeparator,
+        csv_input::{self, CsvHeader},
+        json_input,
+        regex::RegexProcessor,
+        replace::ReplacementValues,
+        BatchInput, ShellCommandAndArgs,
+    },
 };
 
+/// Splits an input line into words like a shell does.
+fn split_words(input_line: &OsStr) -> anyhow::Result<Vec<OsString>> {
+    let words = shlex::bytes::split(input_line.as_encoded_bytes())
+        .context("unbalanced quotes or trailing backslash")?;
+
+    Ok(words.into_iter().map(os_string_from_bytes).collect())
+}
+
 pub struct BufferedInputLineParser {
     split_whitespace: bool,
+    replace_mode: bool,
+    jsonl: bool,
     shell_command_and_args: ShellCommandAndArgs,
-    command_and_initial_arguments: Vec<String>,
+    command_and_initial_arguments: Vec<OsString>,
     regex_processor: RegexProcessor,
+    column_separator: ColumnSeparator,
 }
 
 impl BufferedInputLineParser {
-    pub fn new(command_line_args: &CommandLineArgs, regex_processor: RegexProcessor) -> Self {
+    pub fn new(
+        command_line_args: &CommandLineArgs,
+        regex_processor: RegexProcessor,
+        column_separator: ColumnSeparator,
+    ) -> Self {
         let split_whitespace = !command_line_args.null_separator;
 
         let command_and_initial_arguments = command_line_args.command_and_initial_arguments.clone();
@@ -24,41 +49,318 @@ impl BufferedInputLineParser {
 
         Self {
             split_whitespace,
+            replace_mode: super::replace_mode(command_line_args),
+            jsonl: command_line_args.jsonl,
             shell_command_and_args,
             command_and_initial_arguments,
             regex_processor,
+            column_separator,
         }
     }
 
-    pub fn parse_segment(&self, segment: Vec<u8>) -> Option<OwnedCommandAndArgs> {
-        if let Ok(input_line) = std::str::from_utf8(&segment) {
-            self.parse_line(input_line)
+    /// The command and initial arguments followed by `input_values`.
+    fn append_input_values(
+        &self,
+        input_values: impl IntoIterator<Item = OsString>,
+    ) -> super::CommandArgs {
+        self.command_and_initial_arguments
+            .iter()
+            .cloned()
+            .chain(input_values)
+            .collect()
+    }
+
+    /// Returns the parsed command and the input line it was parsed from.  Returns None
+    /// for a line without a command, e.g. a blank line.
+    pub fn parse_segment(
+        &self,
+        segment: Vec<u8>,
+        sequence_number: usize,
+    ) -> anyhow::Result<Option<(OwnedCommandAndArgs, OsString)>> {
+        let input_line = os_string_from_input(segment)?;
+
+        let command_and_args = self.parse_line(&input_line, sequence_number)?;
+
+        Ok(command_and_args.map(|command_and_args| (command_and_args, input_line)))
+    }
+
+    /// Parses the header row of a --csv input.
+    pub fn parse_csv_header(&self, segment: Vec<u8>) -> anyhow::Result<CsvHeader> {
+        csv_input::parse_header(&segment, &self.command_and_initial_arguments)
+    }
+
+    /// Parses a CSV record of a --csv input, returning the parsed command and the fields
+    /// of the record.  Returns None for a blank line.
+    pub fn parse_csv_segment(
+        &self,
+        csv_header: &CsvHeader,
+        segment: Vec<u8>,
+        sequence_number: usize,
+    ) -> anyhow::Result<Option<(OwnedCommandAndArgs, Vec<String>)>> {
+        let Some(fields) = csv_input::parse_fields(&segment, csv_header)? else {
+            return Ok(None);
+        };
+
+        let cmd_and_args = if csv_header.replace_mode() {
+            super::replace_command_template(
+                &self.command_and_initial_arguments,
+                &fields,
+                csv_header.column_names(),
+                sequence_number,
+            )
         } else {
-            None
+            self.append_input_values(fields.iter().map(OsString::from))
+        };
+
+        let command_and_args =
+            super::build_owned_command_and_args(&self.shell_command_and_args, cmd_and_args)
+                .context("CSV record has an empty command")?;
+
+        Ok(Some((command_and_args, fields)))
+    }
+
+    /// Parses a line of a --jsonl input, returning the parsed command, the input line and
+    /// the options for the job.  Returns None for a blank line.
+    pub fn parse_json_segment(
+        &self,
+        segment: Vec<u8>,
+        sequence_number: usize,
+    ) -> anyhow::Result<Option<(OwnedCommandAndArgs, String, JobOptions)>> {
+        let input_line = json_input::line_from_segment(segment)?;
+
+        if input_line.trim().is_empty() {
+            return Ok(None);
         }
+
+        let json_line = json_input::parse_line(&input_line)?;
+
+        let cmd_and_args = if self.replace_mode {
+            super::replace_command_template_values(
+                &self.command_and_initial_arguments,
+                &ReplacementValues {
+                    input: OsStr::new(&input_line),
+                    input_values: std::slice::from_ref(&input_line),
+                    column_names: &[],
+                    json: Some(&json_line.value),
+                    sequence_number,
+                },
+            )
+        } else {
+            self.append_input_values([OsString::from(&input_line)])
+        };
+
+        let command_and_args =
+            super::build_owned_command_and_args(&self.shell_command_and_args, cmd_and_args)
+                .context("JSON line has an empty command")?;
+
+        Ok(Some((command_and_args, input_line, json_line.job_options)))
     }
 
-    pub fn parse_line(&self, input_line: &str) -> Option<OwnedCommandAndArgs> {
-        let cmd_and_args = if !self.regex_processor.regex_mode() {
-            let mut cmd_and_args = if self.split_whitespace {
-                split(input_line).unwrap()
-            } else {
-                vec![input_line.into()]
+    /// Parses a segment into one input of a batch and the options for its job.
+    /// Returns None for a segment that is skipped.
+    pub fn parse_batch_input(
+        &self,
+        segment: Vec<u8>,
+        csv_header: Option<&CsvHeader>,
+    ) -> anyhow::Result<Option<(BatchInput, JobOptions)>> {
+        if let Some(csv_header) = csv_header {
+            let fields = csv_input::parse_fields(&segment, csv_header)?;
+
+            return Ok(fields.map(|fields| {
+                (
+                    BatchInput::new(fields.into_iter().map_into().collect()),
+                    JobOptions::default(),
+                )
+            }));
+        }
+
+        if self.jsonl {
+            let input_line = json_input::line_from_segment(segment)?;
+
+            if input_line.trim().is_empty() {
+                return Ok(None);
+            }
+
+            let json_line = json_input::parse_line(&input_line)?;
+
+            let batch_input = BatchInput {
+                input_values: vec![input_line.into()],
+                json: Some(json_line.value),
             };
 
-            if !self.command_and_initial_arguments.is_empty() {
-                cmd_and_args = [self.command_and_initial_arguments.clone(), cmd_and_args].concat();
+            return Ok(Some((batch_input, json_line.job_options)));
+        }
+
+        let input_line = os_string_from_input(segment)?;
+
+        let input_values = if self.column_separator.colsep_mode() {
+            self.column_separator.split_columns(&input_line)?
+        } else {
+            // parse_batch splits the line again, but only once the batch is complete.
+            if self.split_whitespace && !self.replace_mode {
+                split_words(&input_line)?;
+            }
+            vec![input_line]
+        };
+
+        Ok(Some((BatchInput::new(input_values), JobOptions::default())))
+    }
+
+    /// Parses one segment from each input file read side by side into one input of
+    /// a batch.
+    pub fn parse_side_by_side_batch_input(
+        &self,
+        segments: Vec<Vec<u8>>,
+    ) -> anyhow::Result<BatchInput> {
+        self.side_by_side_input_values(segments)
+            .map(BatchInput::new)
+    }
+
+    /// Builds the command for a batch of inputs parsed by `parse_batch_input`.
+    pub fn parse_batch(
+        &self,
+        batch: &[BatchInput],
+        csv_header: Option<&CsvHeader>,
+        sequence_number: usize,
+    ) -> Option<OwnedCommandAndArgs> {
+        let (replace_mode, column_names) = match csv_header {
+            Some(csv_header) => (csv_header.replace_mode(), csv_header.column_names()),
+            None => (self.replace_mode, &[][..]),
+        };
+
+        let cmd_and_args = if replace_mode {
+            super::replace_batch_command_template(
+                &self.command_and_initial_arguments,
+                batch,
+                column_names,
+                self.jsonl,
+                sequence_number,
+            )
+        } else {
+            // Plain input lines are split like a single input, other inputs are
+            // appended value by value.
+            let split_input_lines = self.split_whitespace
+                && csv_header.is_none()
+                && !self.jsonl
+                && !self.column_separator.colsep_mode();
+
+            let mut input_values = vec![];
+
+            for batch_input in batch {
+                for input_value in &batch_input.input_values {
+                    if split_input_lines {
+                        input_values.extend(split_words(input_value).ok()?);
+                    } else {
+                        input_values.push(input_value.clone());
+                    }
+                }
+            }
+
+            self.append_input_values(input_values)
+        };
+
+        super::build_owned_command_and_args(&self.shell_command_and_args, cmd_and_args)
+    }
+
+    fn side_by_side_input_values(&self, segments: Vec<Vec<u8>>) -> anyhow::Result<Vec<OsString>> {
+        let mut input_values = Vec::with_capacity(segments.len());
+
+        for segment in segments {
+            let input_value = os_string_from_input(segment)?;
+
+            if self.column_separator.colsep_mode() {
+                input_values.extend(self.column_separator.split_columns(&input_value)?);
+            } else {
+                input_values.push(input_value);
             }
+        }
+
+        Ok(input_values)
+    }
 
-            cmd_and_args
+    /// Parses one segment from each input file read side by side, returning the
+    /// parsed command and the input values.  Returns None if there is no command.
+    pub fn parse_side_by_side_segments(
+        &self,
+        segments: Vec<Vec<u8>>,
+        sequence_number: usize,
+    ) -> anyhow::Result<Option<(OwnedCommandAndArgs, Vec<OsString>)>> {
+        let input_values = self.side_by_side_input_values(segments)?;
+
+        let cmd_and_args = super::replace_command_template(
+            &self.command_and_initial_arguments,
+            &input_values,
+            &[],
+            sequence_number,
+        );
+
+        let command_and_args =
+            super::build_owned_command_and_args(&self.shell_command_and_args, cmd_and_args);
+
+        Ok(command_and_args.map(|command_and_args| (command_and_args, input_values)))
+    }
+
+    /// Builds the command for input written to its stdin in --pipe or --stdin-input
+    /// mode.  The input isn't part of the command, so only {#} is replaced.
+    pub fn parse_stdin_command(&self, sequence_number: usize) -> Option<OwnedCommandAndArgs> {
+        let cmd_and_args = if self.replace_mode {
+            super::replace_command_template::<OsString>(
+                &self.command_and_initial_arguments,
+                &[],
+                &[],
+                sequence_number,
+            )
         } else {
+            self.append_input_values([])
+        };
+
+        super::build_owned_command_and_args(&self.shell_command_and_args, cmd_and_args)
+    }
+
+    /// Parses the command for one input line, which needn't be UTF-8.  Returns None for
+    /// a line without a command, e.g. a blank line.
+    pub fn parse_line(
+        &self,
+        input_line: impl AsRef<OsStr>,
+        sequence_number: usize,
+    ) -> anyhow::Result<Option<OwnedCommandAndArgs>> {
+        let input_line = input_line.as_ref();
+
+        let cmd_and_args = if self.regex_processor.regex_mode() {
             self.command_and_initial_arguments
                 .iter()
                 .map(|arg| self.regex_processor.process_string(arg, input_line).into())
-                .collect_vec()
+                .collect()
+        } else if self.column_separator.colsep_mode() {
+            let columns = self.column_separator.split_columns(input_line)?;
+
+            if self.replace_mode {
+                super::replace_command_template(
+                    &self.command_and_initial_arguments,
+                    &columns,
+                    &[],
+                    sequence_number,
+                )
+            } else {
+                self.append_input_values(columns)
+            }
+        } else if self.replace_mode {
+            super::replace_command_template(
+                &self.command_and_initial_arguments,
+                &[input_line],
+                &[],
+                sequence_number,
+            )
+        } else if self.split_whitespace {
+            self.append_input_values(split_words(input_line)?)
+        } else {
+            self.append_input_values([input_line.to_owned()])
         };
 
-        super::build_owned_command_and_args(&self.shell_command_and_args, cmd_and_args)
+        Ok(super::build_owned_command_and_args(
+            &self.shell_command_and_args,
+            cmd_and_args,
+        ))
     }
 }
 
@@ -68,6 +370,8 @@ mod test {
 
     use std::{default::Default, path::PathBuf};
 
+    use crate::parser::InvalidJsonError;
+
     #[test]
     fn test_split_whitespace() {
         let command_line_args = CommandLineArgs {
@@ -80,39 +384,43 @@ mod test {
         let parser = BufferedInputLineParser::new(
             &command_line_args,
             RegexProcessor::new(&command_line_args).unwrap(),
+            ColumnSeparator::new(&command_line_args).unwrap(),
         );
 
-        let result = parser.parse_line("echo hi there");
+        let result = parser.parse_line("echo hi there", 1).unwrap();
 
         assert_eq!(
             result,
             Some(OwnedCommandAndArgs {
                 command_path: PathBuf::from("echo"),
                 args: vec!["hi", "there"].into_iter().map_into().collect(),
+                job_slots: vec![],
             })
         );
 
-        let result = parser.parse_line(" echo  hi    there  ");
+        let result = parser.parse_line(" echo  hi    there  ", 1).unwrap();
 
         assert_eq!(
             result,
             Some(OwnedCommandAndArgs {
                 command_path: PathBuf::from("echo"),
                 args: vec!["hi", "there"].into_iter().map_into().collect(),
+                job_slots: vec![],
             })
         );
 
-        let result = parser.parse_line(" /bin/echo ");
+        let result = parser.parse_line(" /bin/echo ", 1).unwrap();
 
         assert_eq!(
             result,
             Some(OwnedCommandAndArgs {
                 command_path: PathBuf::from("/bin/echo"),
                 args: vec![],
+                job_slots: vec![],
             })
         );
 
-        let result = parser.parse_line("");
+        let result = parser.parse_line("", 1).unwrap();
 
         assert_eq!(result, None);
     }
@@ -122,16 +430,17 @@ mod test {
         let command_line_args = CommandLineArgs {
             null_separator: true,
             shell: false,
-            command_and_initial_arguments: vec!["gzip".to_owned(), "-k".to_owned()],
+            command_and_initial_arguments: vec!["gzip".into(), "-k".into()],
             ..Default::default()
         };
 
         let parser = BufferedInputLineParser::new(
             &command_line_args,
             RegexProcessor::new(&command_line_args).unwrap(),
+            ColumnSeparator::new(&command_line_args).unwrap(),
         );
 
-        let result = parser.parse_line("file with spaces");
+        let result = parser.parse_line("file with spaces", 1).unwrap();
 
         assert_eq!(
             result,
@@ -141,6 +450,7 @@ mod test {
                     .into_iter()
                     .map_into()
                     .collect(),
+                job_slots: vec![],
             })
         );
     }
@@ -158,9 +468,10 @@ mod test {
         let parser = BufferedInputLineParser::new(
             &command_line_args,
             RegexProcessor::new(&command_line_args).unwrap(),
+            ColumnSeparator::new(&command_line_args).unwrap(),
         );
 
-        let result = parser.parse_line("awesomebashfunction 1 2 3");
+        let result = parser.parse_line("awesomebashfunction 1 2 3", 1).unwrap();
 
         assert_eq!(
             result,
@@ -170,6 +481,7 @@ mod test {
                     .into_iter()
                     .map_into()
                     .collect(),
+                job_slots: vec![],
             })
         );
 
@@ -184,9 +496,10 @@ mod test {
         let parser = BufferedInputLineParser::new(
             &command_line_args,
             RegexProcessor::new(&command_line_args).unwrap(),
+            ColumnSeparator::new(&command_line_args).unwrap(),
         );
 
-        let result = parser.parse_line(" awesomebashfunction 1 2 3 ");
+        let result = parser.parse_line(" awesomebashfunction 1 2 3 ", 1).unwrap();
 
         assert_eq!(
             result,
@@ -196,6 +509,7 @@ mod test {
                     .into_iter()
                     .map_into()
                     .collect(),
+                job_slots: vec![],
             })
         );
     }
@@ -205,26 +519,28 @@ mod test {
         let command_line_args = CommandLineArgs {
             null_separator: false,
             shell: false,
-            command_and_initial_arguments: vec!["md5".to_owned(), "-s".to_owned()],
+            command_and_initial_arguments: vec!["md5".into(), "-s".into()],
             ..Default::default()
         };
 
         let parser = BufferedInputLineParser::new(
             &command_line_args,
             RegexProcessor::new(&command_line_args).unwrap(),
+            ColumnSeparator::new(&command_line_args).unwrap(),
         );
 
-        let result = parser.parse_line("stuff");
+        let result = parser.parse_line("stuff", 1).unwrap();
 
         assert_eq!(
             result,
             Some(OwnedCommandAndArgs {
                 command_path: PathBuf::from("md5"),
                 args: vec!["-s", "stuff"].into_iter().map_into().collect(),
+                job_slots: vec![],
             })
         );
 
-        let result = parser.parse_line(" stuff things ");
+        let result = parser.parse_line(" stuff things ", 1).unwrap();
 
         assert_eq!(
             result,
@@ -234,6 +550,7 @@ mod test {
                     .into_iter()
                     .map_into()
                     .collect(),
+                job_slots: vec![],
             })
         );
     }
@@ -242,8 +559,8 @@ mod test {
     fn test_regex_named_groups() {
         let command_line_args = CommandLineArgs {
             command_and_initial_arguments: vec![
-                "echo".to_owned(),
-                "got arg1={arg1} arg2={arg2}".to_owned(),
+                "echo".into(),
+                "got arg1={arg1} arg2={arg2}".into(),
             ],
             regex: Some("(?P<arg1>.*),(?P<arg2>.*)".to_owned()),
             ..Default::default()
@@ -252,9 +569,10 @@ mod test {
         let parser = BufferedInputLineParser::new(
             &command_line_args,
             RegexProcessor::new(&command_line_args).unwrap(),
+            ColumnSeparator::new(&command_line_args).unwrap(),
         );
 
-        let result = parser.parse_line("foo,bar");
+        let result = parser.parse_line("foo,bar", 1).unwrap();
 
         assert_eq!(
             result,
@@ -264,6 +582,7 @@ mod test {
                     .into_iter()
                     .map_into()
                     .collect(),
+                job_slots: vec![],
             })
         );
     }
@@ -272,8 +591,8 @@ mod test {
     fn test_regex_numbered_groups() {
         let command_line_args = CommandLineArgs {
             command_and_initial_arguments: vec![
-                "echo".to_owned(),
-                "got arg1={2} arg2={1} arg3={0}".to_owned(),
+                "echo".into(),
+                "got arg1={2} arg2={1} arg3={0}".into(),
             ],
             regex: Some("(.*),(.*)".to_owned()),
             ..Default::default()
@@ -282,9 +601,10 @@ mod test {
         let parser = BufferedInputLineParser::new(
             &command_line_args,
             RegexProcessor::new(&command_line_args).unwrap(),
+            ColumnSeparator::new(&command_line_args).unwrap(),
         );
 
-        let result = parser.parse_line("foo,bar");
+        let result = parser.parse_line("foo,bar", 1).unwrap();
 
         assert_eq!(
             result,
@@ -294,7 +614,98 @@ mod test {
                     .into_iter()
                     .map_into()
                     .collect(),
+                job_slots: vec![],
+            })
+        );
+    }
+
+    #[test]
+    fn test_colsep() {
+        let command_line_args = CommandLineArgs {
+            command_and_initial_arguments: vec!["cp".into(), "{2}".into(), "{1/}".into()],
+            colsep: Some("\t".to_owned()),
+            ..Default::default()
+        };
+
+        let parser = BufferedInputLineParser::new(
+            &command_line_args,
+            RegexProcessor::new(&command_line_args).unwrap(),
+            ColumnSeparator::new(&command_line_args).unwrap(),
+        );
+
+        let result = parser.parse_line("out/a.txt\tin/a.txt", 1).unwrap();
+
+        assert_eq!(
+            result,
+            Some(OwnedCommandAndArgs {
+                command_path: PathBuf::from("cp"),
+                args: vec!["in/a.txt", "a.txt"].into_iter().map_into().collect(),
+                job_slots: vec![],
+            })
+        );
+
+        let error = parser.parse_line("out/a.txt", 1).unwrap_err();
+
+        assert_eq!(
+            error.to_string(),
+            r#"input line has 1 columns but the command uses {2}: "out/a.txt""#
+        );
+    }
+
+    #[test]
+    fn test_colsep_append_columns() {
+        let command_line_args = CommandLineArgs {
+            command_and_initial_arguments: vec!["echo".into()],
+            colsep: Some(",".to_owned()),
+            ..Default::default()
+        };
+
+        let parser = BufferedInputLineParser::new(
+            &command_line_args,
+            RegexProcessor::new(&command_line_args).unwrap(),
+            ColumnSeparator::new(&command_line_args).unwrap(),
+        );
+
+        let result = parser.parse_line("a b,c", 1).unwrap();
+
+        assert_eq!(
+            result,
+            Some(OwnedCommandAndArgs {
+                command_path: PathBuf::from("echo"),
+                args: vec!["a b", "c"].into_iter().map_into().collect(),
+                job_slots: vec![],
             })
         );
     }
+
+    #[test]
+    fn test_invalid_json_errors() {
+        let command_line_args = CommandLineArgs {
+            command_and_initial_arguments: vec!["echo".into()],
+            jsonl: true,
+            ..Default::default()
+        };
+
+        let parser = BufferedInputLineParser::new(
+            &command_line_args,
+            RegexProcessor::new(&command_line_args).unwrap(),
+            ColumnSeparator::new(&command_line_args).unwrap(),
+        );
+
+        for segment in [&b"{\"id\": "[..], b"[1, 2]", b"\xff"] {
+            let error = parser.parse_json_segment(segment.to_vec(), 1).unwrap_err();
+            assert!(error.is::<InvalidJsonError>());
+        }
+
+        let command_line_args = CommandLineArgs::default();
+
+        let parser = BufferedInputLineParser::new(
+            &command_line_args,
+            RegexProcessor::new(&command_line_args).unwrap(),
+            ColumnSeparator::new(&command_line_args).unwrap(),
+        );
+
+        let error = parser.parse_line("echo \"a", 1).unwrap_err();
+        assert!(!error.is::<InvalidJsonError>());
+    }
 }
@@ -0,0 +1,122 @@
+use anyhow::{bail, Context};
+
+use std::ffi::{OsStr, OsString};
+
+use crate::{command_line_args::CommandLineArgs, common::os_string_from_bytes};
+
+/// Splits input lines into columns for --colsep.  Lines are split as bytes, so they
+/// needn't be UTF-8.
+#[derive(Clone)]
+pub struct ColumnSeparator {
+    regex: Option<regex::bytes::Regex>,
+    required_columns: usize,
+}
+
+impl ColumnSeparator {
+    pub fn new(command_line_args: &CommandLineArgs) -> anyhow::Result<Self> {
+        let regex = match &command_line_args.colsep {
+            None => None,
+            Some(colsep) => Some(
+                regex::bytes::Regex::new(colsep)
+                    .context("ColumnSeparator::new: error creating colsep regex")?,
+            ),
+        };
+
+        let required_columns = if super::replace_mode(command_line_args) {
+            super::replace::max_positional_index(super::command_template(command_line_args))
+                .unwrap_or_default()
+        } else {
+            0
+        };
+
+        Ok(Self {
+            regex,
+            required_columns,
+        })
+    }
+
+    pub fn colsep_mode(&self) -> bool {
+        self.regex.is_some()
+    }
+
+    /// Splits `input_line` into columns, failing if there are fewer columns than the
+    /// command uses.
+    pub fn split_columns(&self, input_line: &OsStr) -> anyhow::Result<Vec<OsString>> {
+        let columns: Vec<OsString> = match &self.regex {
+            None => vec![input_line.to_owned()],
+            Some(regex) => regex
+                .split(input_line.as_encoded_bytes())
+                .map(|column| os_string_from_bytes(column.to_vec()))
+                .collect(),
+        };
+
+        if columns.len() < self.required_columns {
+            bail!(
+                "input line has {} columns but the command uses {{{}}}: {:?}",
+                columns.len(),
+                self.required_columns,
+                input_line
+            );
+        }
+
+        Ok(columns)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_colsep_disabled() {
+        let command_line_args = CommandLineArgs::default();
+
+        let column_separator = ColumnSeparator::new(&command_line_args).unwrap();
+
+        assert!(!column_separator.colsep_mode());
+
+        assert_eq!(
+            column_separator.split_columns(OsStr::new("a\tb")).unwrap(),
+            vec!["a\tb"]
+        );
+    }
+
+    #[test]
+    fn test_colsep_split_columns() {
+        let command_line_args = CommandLineArgs {
+            colsep: Some(r"\s*,\s*".to_owned()),
+            command_and_initial_arguments: vec!["cp".into(), "{1}".into(), "{3}".into()],
+            ..Default::default()
+        };
+
+        let column_separator = ColumnSeparator::new(&command_line_args).unwrap();
+
+        assert!(column_separator.colsep_mode());
+
+        assert_eq!(
+            column_separator
+                .split_columns(OsStr::new("a , b,c,"))
+                .unwrap(),
+            vec!["a", "b", "c", ""]
+        );
+
+        let error = column_separator
+            .split_columns(OsStr::new("a,b"))
+            .unwrap_err();
+
+        assert_eq!(
+            error.to_string(),
+            r#"input line has 2 columns but the command uses {3}: "a,b""#
+        );
+    }
+
+    #[test]
+    fn test_colsep_invalid() {
+        let command_line_args = CommandLineArgs {
+            colsep: Some("(".to_owned()),
+            ..Default::default()
+        };
+
+        assert!(ColumnSeparator::new(&command_line_args).is_err());
+    }
+}
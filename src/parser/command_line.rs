@@ -1,103 +1,312 @@
+use anyhow::{bail, Context};
+
 use itertools::Itertools;
 
-use std::collections::VecDeque;
+use std::{ffi::OsString, ops::Range};
 
 use crate::{
-    command_line_args::{CommandLineArgs, COMMANDS_FROM_ARGS_SEPARATOR},
-    common::OwnedCommandAndArgs,
-    parser::{regex::RegexProcessor, ShellCommandAndArgs},
+    command_line_args::{ArgumentGroupSeparator, CommandLineArgs, LinkMismatch},
+    common::{join_os_strings, os_string_from_input, OwnedCommandAndArgs},
+    input::{BufferedInput, BufferedInputReader},
+    parser::{regex::RegexProcessor, BatchInput, ShellCommandAndArgs},
 };
 
+/// Linked groups whose values are taken together, row by row.
+#[derive(Debug)]
+struct LinkedGroupSet {
+    groups: Vec<Vec<OsString>>,
+    rows: usize,
+}
+
+impl LinkedGroupSet {
+    /// One value from each group, shorter groups wrap around.
+    fn row(&self, row: usize) -> impl Iterator<Item = &OsString> {
+        self.groups
+            .iter()
+            .map(move |group| &group[row % group.len()])
+    }
+}
+
+/// Cartesian product of linked group sets, iterated lazily with odometer style
+/// indices where the last set changes fastest.
 #[derive(Debug)]
 struct ArgumentGroups {
-    first_command_and_args: Vec<String>,
-    all_argument_groups: VecDeque<Vec<String>>,
+    first_command_and_args: Vec<OsString>,
+    linked_group_sets: Vec<LinkedGroupSet>,
+    indices: Vec<usize>,
+    remaining: usize,
+}
+
+impl ArgumentGroups {
+    fn new(
+        first_command_and_args: Vec<OsString>,
+        linked_group_sets: Vec<LinkedGroupSet>,
+    ) -> anyhow::Result<Self> {
+        let remaining = if linked_group_sets.is_empty() {
+            0
+        } else {
+            linked_group_sets
+                .iter()
+                .try_fold(1usize, |product, set| product.checked_mul(set.rows))
+                .context("too many combinations of argument groups")?
+        };
+
+        Ok(Self {
+            first_command_and_args,
+            indices: vec![0; linked_group_sets.len()],
+            linked_group_sets,
+            remaining,
+        })
+    }
+
+    fn next_argument_group(&mut self) -> Option<Vec<OsString>> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+
+        let argument_group = self
+            .linked_group_sets
+            .iter()
+            .zip(&self.indices)
+            .flat_map(|(set, &row)| set.row(row))
+            .cloned()
+            .collect();
+
+        for (set, index) in self.linked_group_sets.iter().zip(&mut self.indices).rev() {
+            *index += 1;
+            if *index < set.rows {
+                break;
+            }
+            *index = 0;
+        }
+
+        Some(argument_group)
+    }
 }
 
 pub struct CommandLineArgsParser {
     argument_groups: ArgumentGroups,
+    replace_mode: bool,
     shell_command_and_args: ShellCommandAndArgs,
     regex_processor: RegexProcessor,
 }
 
 impl CommandLineArgsParser {
-    pub fn new(command_line_args: &CommandLineArgs, regex_processor: RegexProcessor) -> Self {
-        let argument_groups = Self::build_argument_groups(command_line_args);
+    pub async fn new(
+        command_line_args: &'static CommandLineArgs,
+        regex_processor: RegexProcessor,
+    ) -> anyhow::Result<Self> {
+        let argument_groups = Self::build_argument_groups(command_line_args).await?;
 
         let shell_command_and_args = ShellCommandAndArgs::new(command_line_args);
 
-        Self {
+        Ok(Self {
             argument_groups,
+            replace_mode: super::replace_mode(command_line_args),
             shell_command_and_args,
             regex_processor,
+        })
+    }
+
+    /// Pairs up the values of linked groups in order.  Each row of the returned set has
+    /// one value from each group.
+    fn link_groups(
+        linked_groups: Vec<Vec<OsString>>,
+        link_mismatch: LinkMismatch,
+    ) -> anyhow::Result<LinkedGroupSet> {
+        let lengths = linked_groups.iter().map(Vec::len).collect_vec();
+
+        let max_length = lengths.iter().copied().max().unwrap_or_default();
+
+        if link_mismatch == LinkMismatch::Error && lengths.iter().any(|len| *len != max_length) {
+            bail!(
+                "linked argument groups have different lengths {:?}, use --link-mismatch=wrap to reuse values",
+                lengths
+            );
+        }
+
+        Ok(LinkedGroupSet {
+            groups: linked_groups,
+            rows: max_length,
+        })
+    }
+
+    /// Reads one value per line from each of `file_names`.
+    async fn read_argument_files(
+        file_names: &'static [OsString],
+        command_line_args: &CommandLineArgs,
+    ) -> anyhow::Result<Vec<OsString>> {
+        let mut values = vec![];
+
+        for file_name in file_names {
+            let file_name = file_name
+                .to_str()
+                .with_context(|| format!("argument file name {:?} is not UTF-8", file_name))?;
+
+            let buffered_input = if file_name == "-" {
+                BufferedInput::Stdin
+            } else {
+                BufferedInput::File { file_name }
+            };
+
+            let mut input_reader =
+                BufferedInputReader::new(buffered_input, command_line_args).await?;
+
+            while let Some((input_line_number, segment)) = input_reader
+                .next_segment()
+                .await
+                .context("next_segment error")?
+            {
+                let value = os_string_from_input(segment).with_context(|| {
+                    format!("argument file line {} can't be parsed", input_line_number)
+                })?;
+                values.push(value);
+            }
         }
+
+        Ok(values)
     }
 
-    fn build_argument_groups(command_line_args: &CommandLineArgs) -> ArgumentGroups {
+    async fn build_argument_groups(
+        command_line_args: &'static CommandLineArgs,
+    ) -> anyhow::Result<ArgumentGroups> {
         let command_and_initial_arguments = &command_line_args.command_and_initial_arguments;
 
-        let mut remaining_argument_groups = Vec::with_capacity(command_and_initial_arguments.len());
+        let mut first_command_and_args: &[OsString] = command_and_initial_arguments;
 
-        let mut first = true;
+        // Separators with the range of arguments that follow them.
+        let mut separated_groups: Vec<(ArgumentGroupSeparator, Range<usize>)> = vec![];
 
-        let mut first_command_and_args = vec![];
+        for (i, arg) in command_and_initial_arguments.iter().enumerate() {
+            let Some(separator) = ArgumentGroupSeparator::parse(arg) else {
+                continue;
+            };
 
-        for (separator, group) in &command_and_initial_arguments
-            .iter()
-            .group_by(|arg| *arg == COMMANDS_FROM_ARGS_SEPARATOR)
-        {
-            let group_vec = group.cloned().collect();
-
-            if first {
-                if !separator {
-                    first_command_and_args = group_vec;
-                }
-                first = false;
-            } else if !separator {
-                remaining_argument_groups.push(group_vec);
+            match separated_groups.last_mut() {
+                Some((_, range)) => range.end = i,
+                None => first_command_and_args = &command_and_initial_arguments[..i],
             }
+
+            separated_groups.push((separator, i + 1..command_and_initial_arguments.len()));
         }
 
-        let all_argument_groups = remaining_argument_groups
-            .into_iter()
-            .multi_cartesian_product()
-            .collect();
+        // Each entry is a set of linked groups, sets are combined as a cartesian product.
+        let mut linked_group_sets: Vec<Vec<Vec<OsString>>> = vec![];
 
-        ArgumentGroups {
-            first_command_and_args,
-            all_argument_groups,
+        for (separator, range) in separated_groups {
+            let args = &command_and_initial_arguments[range];
+
+            let group = if separator.from_files {
+                Self::read_argument_files(args, command_line_args).await?
+            } else {
+                args.to_vec()
+            };
+
+            if group.is_empty() {
+                continue;
+            }
+
+            match linked_group_sets.last_mut() {
+                Some(linked_groups) if separator.linked => linked_groups.push(group),
+                _ => linked_group_sets.push(vec![group]),
+            }
         }
+
+        let linked_group_sets = linked_group_sets
+            .into_iter()
+            .map(|linked_groups| Self::link_groups(linked_groups, command_line_args.link_mismatch))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        ArgumentGroups::new(first_command_and_args.to_vec(), linked_group_sets)
     }
 
-    fn parse_argument_group(&self, argument_group: Vec<String>) -> Option<OwnedCommandAndArgs> {
-        let cmd_and_args = if !self.regex_processor.regex_mode() {
-            [
-                self.argument_groups.first_command_and_args.clone(),
-                argument_group,
-            ]
-            .concat()
-        } else {
-            let input_line = argument_group.join(" ");
+    fn parse_argument_group(
+        &self,
+        argument_group: Vec<OsString>,
+        sequence_number: usize,
+    ) -> Option<OwnedCommandAndArgs> {
+        let cmd_and_args = if self.regex_processor.regex_mode() {
+            let input_line = join_os_strings(&argument_group);
 
             self.argument_groups
                 .first_command_and_args
                 .iter()
                 .map(|arg| self.regex_processor.process_string(arg, &input_line).into())
-                .collect_vec()
+                .collect()
+        } else if self.replace_mode {
+            super::replace_command_template(
+                &self.argument_groups.first_command_and_args,
+                &argument_group,
+                &[],
+                sequence_number,
+            )
+        } else {
+            self.argument_groups
+                .first_command_and_args
+                .iter()
+                .chain(&argument_group)
+                .cloned()
+                .collect()
         };
 
         super::build_owned_command_and_args(&self.shell_command_and_args, cmd_and_args)
     }
 
     pub fn has_remaining_argument_groups(&self) -> bool {
-        !self.argument_groups.all_argument_groups.is_empty()
+        self.argument_groups.remaining > 0
     }
 
-    pub fn parse_next_argument_group(&mut self) -> Option<OwnedCommandAndArgs> {
-        match self.argument_groups.all_argument_groups.pop_front() {
-            None => None,
-            Some(argument_group) => self.parse_argument_group(argument_group),
-        }
+    /// Number of argument groups not yet parsed.
+    pub fn remaining_argument_groups(&self) -> usize {
+        self.argument_groups.remaining
+    }
+
+    /// Returns the next argument group as one input of a batch.
+    pub fn next_batch_input(&mut self) -> Option<BatchInput> {
+        self.argument_groups
+            .next_argument_group()
+            .map(BatchInput::new)
+    }
+
+    /// Builds the command for a batch of argument groups.
+    pub fn parse_batch(
+        &self,
+        batch: &[BatchInput],
+        sequence_number: usize,
+    ) -> Option<OwnedCommandAndArgs> {
+        let first_command_and_args = &self.argument_groups.first_command_and_args;
+
+        let cmd_and_args = if self.replace_mode {
+            super::replace_batch_command_template(
+                first_command_and_args,
+                batch,
+                &[],
+                false,
+                sequence_number,
+            )
+        } else {
+            first_command_and_args
+                .iter()
+                .chain(batch.iter().flat_map(BatchInput::input_values))
+                .cloned()
+                .collect()
+        };
+
+        super::build_owned_command_and_args(&self.shell_command_and_args, cmd_and_args)
+    }
+
+    /// Returns the parsed command and the argument group it was parsed from.
+    pub fn parse_next_argument_group(
+        &mut self,
+        sequence_number: usize,
+    ) -> Option<(OwnedCommandAndArgs, Vec<OsString>)> {
+        let argument_group = self.argument_groups.next_argument_group()?;
+
+        let command_and_args =
+            self.parse_argument_group(argument_group.clone(), sequence_number)?;
+
+        Some((command_and_args, argument_group))
     }
 }
 
@@ -105,13 +314,26 @@ impl CommandLineArgsParser {
 mod test {
     use super::*;
 
-    use std::{default::Default, path::PathBuf};
+    use std::{default::Default, ffi::OsStr, path::PathBuf};
+
+    fn new_parser(command_line_args: CommandLineArgs) -> anyhow::Result<CommandLineArgsParser> {
+        let command_line_args: &'static CommandLineArgs = Box::leak(Box::new(command_line_args));
+
+        let regex_processor = RegexProcessor::new(command_line_args).unwrap();
+
+        tokio::runtime::Runtime::new()
+            .unwrap()
+            .block_on(CommandLineArgsParser::new(
+                command_line_args,
+                regex_processor,
+            ))
+    }
 
     fn collect_into_vec(mut parser: CommandLineArgsParser) -> Vec<OwnedCommandAndArgs> {
         let mut result = vec![];
 
         while parser.has_remaining_argument_groups() {
-            let Some(cmd_and_args) = parser.parse_next_argument_group() else {
+            let Some((cmd_and_args, _)) = parser.parse_next_argument_group(result.len() + 1) else {
                 continue;
             };
 
@@ -134,10 +356,7 @@ mod test {
             ..Default::default()
         };
 
-        let parser = CommandLineArgsParser::new(
-            &command_line_args,
-            RegexProcessor::new(&command_line_args).unwrap(),
-        );
+        let parser = new_parser(command_line_args).unwrap();
 
         let result = collect_into_vec(parser);
 
@@ -147,26 +366,32 @@ mod test {
                 OwnedCommandAndArgs {
                     command_path: PathBuf::from("echo"),
                     args: vec!["-n", "A", "C"].into_iter().map_into().collect(),
+                    job_slots: vec![],
                 },
                 OwnedCommandAndArgs {
                     command_path: PathBuf::from("echo"),
                     args: vec!["-n", "A", "D"].into_iter().map_into().collect(),
+                    job_slots: vec![],
                 },
                 OwnedCommandAndArgs {
                     command_path: PathBuf::from("echo"),
                     args: vec!["-n", "A", "E"].into_iter().map_into().collect(),
+                    job_slots: vec![],
                 },
                 OwnedCommandAndArgs {
                     command_path: PathBuf::from("echo"),
                     args: vec!["-n", "B", "C"].into_iter().map_into().collect(),
+                    job_slots: vec![],
                 },
                 OwnedCommandAndArgs {
                     command_path: PathBuf::from("echo"),
                     args: vec!["-n", "B", "D"].into_iter().map_into().collect(),
+                    job_slots: vec![],
                 },
                 OwnedCommandAndArgs {
                     command_path: PathBuf::from("echo"),
                     args: vec!["-n", "B", "E"].into_iter().map_into().collect(),
+                    job_slots: vec![],
                 },
             ]
         );
@@ -185,10 +410,7 @@ mod test {
             ..Default::default()
         };
 
-        let parser = CommandLineArgsParser::new(
-            &command_line_args,
-            RegexProcessor::new(&command_line_args).unwrap(),
-        );
+        let parser = new_parser(command_line_args).unwrap();
 
         let result = collect_into_vec(parser);
 
@@ -198,26 +420,32 @@ mod test {
                 OwnedCommandAndArgs {
                     command_path: PathBuf::from("echo"),
                     args: vec!["arg1"].into_iter().map_into().collect(),
+                    job_slots: vec![],
                 },
                 OwnedCommandAndArgs {
                     command_path: PathBuf::from("echo"),
                     args: vec!["arg2"].into_iter().map_into().collect(),
+                    job_slots: vec![],
                 },
                 OwnedCommandAndArgs {
                     command_path: PathBuf::from("echo"),
                     args: vec!["arg3"].into_iter().map_into().collect(),
+                    job_slots: vec![],
                 },
                 OwnedCommandAndArgs {
                     command_path: PathBuf::from("say"),
                     args: vec!["arg1"].into_iter().map_into().collect(),
+                    job_slots: vec![],
                 },
                 OwnedCommandAndArgs {
                     command_path: PathBuf::from("say"),
                     args: vec!["arg2"].into_iter().map_into().collect(),
+                    job_slots: vec![],
                 },
                 OwnedCommandAndArgs {
                     command_path: PathBuf::from("say"),
                     args: vec!["arg3"].into_iter().map_into().collect(),
+                    job_slots: vec![],
                 },
             ]
         );
@@ -231,10 +459,7 @@ mod test {
             ..Default::default()
         };
 
-        let parser = CommandLineArgsParser::new(
-            &command_line_args,
-            RegexProcessor::new(&command_line_args).unwrap(),
-        );
+        let parser = new_parser(command_line_args).unwrap();
 
         let result = collect_into_vec(parser);
 
@@ -249,10 +474,7 @@ mod test {
             ..Default::default()
         };
 
-        let parser = CommandLineArgsParser::new(
-            &command_line_args,
-            RegexProcessor::new(&command_line_args).unwrap(),
-        );
+        let parser = new_parser(command_line_args).unwrap();
 
         let result = collect_into_vec(parser);
 
@@ -273,10 +495,7 @@ mod test {
             ..Default::default()
         };
 
-        let parser = CommandLineArgsParser::new(
-            &command_line_args,
-            RegexProcessor::new(&command_line_args).unwrap(),
-        );
+        let parser = new_parser(command_line_args).unwrap();
 
         let result = collect_into_vec(parser);
 
@@ -286,26 +505,32 @@ mod test {
                 OwnedCommandAndArgs {
                     command_path: PathBuf::from("/bin/bash"),
                     args: vec!["-c", "echo -n A C"].into_iter().map_into().collect(),
+                    job_slots: vec![],
                 },
                 OwnedCommandAndArgs {
                     command_path: PathBuf::from("/bin/bash"),
                     args: vec!["-c", "echo -n A D"].into_iter().map_into().collect(),
+                    job_slots: vec![],
                 },
                 OwnedCommandAndArgs {
                     command_path: PathBuf::from("/bin/bash"),
                     args: vec!["-c", "echo -n A E"].into_iter().map_into().collect(),
+                    job_slots: vec![],
                 },
                 OwnedCommandAndArgs {
                     command_path: PathBuf::from("/bin/bash"),
                     args: vec!["-c", "echo -n B C"].into_iter().map_into().collect(),
+                    job_slots: vec![],
                 },
                 OwnedCommandAndArgs {
                     command_path: PathBuf::from("/bin/bash"),
                     args: vec!["-c", "echo -n B D"].into_iter().map_into().collect(),
+                    job_slots: vec![],
                 },
                 OwnedCommandAndArgs {
                     command_path: PathBuf::from("/bin/bash"),
                     args: vec!["-c", "echo -n B E"].into_iter().map_into().collect(),
+                    job_slots: vec![],
                 },
             ]
         );
@@ -323,10 +548,7 @@ mod test {
             ..Default::default()
         };
 
-        let parser = CommandLineArgsParser::new(
-            &command_line_args,
-            RegexProcessor::new(&command_line_args).unwrap(),
-        );
+        let parser = new_parser(command_line_args).unwrap();
 
         let result = collect_into_vec(parser);
 
@@ -336,26 +558,32 @@ mod test {
                 OwnedCommandAndArgs {
                     command_path: PathBuf::from("/bin/bash"),
                     args: vec!["-c", "say C"].into_iter().map_into().collect(),
+                    job_slots: vec![],
                 },
                 OwnedCommandAndArgs {
                     command_path: PathBuf::from("/bin/bash"),
                     args: vec!["-c", "say D"].into_iter().map_into().collect(),
+                    job_slots: vec![],
                 },
                 OwnedCommandAndArgs {
                     command_path: PathBuf::from("/bin/bash"),
                     args: vec!["-c", "say E"].into_iter().map_into().collect(),
+                    job_slots: vec![],
                 },
                 OwnedCommandAndArgs {
                     command_path: PathBuf::from("/bin/bash"),
                     args: vec!["-c", "echo C"].into_iter().map_into().collect(),
+                    job_slots: vec![],
                 },
                 OwnedCommandAndArgs {
                     command_path: PathBuf::from("/bin/bash"),
                     args: vec!["-c", "echo D"].into_iter().map_into().collect(),
+                    job_slots: vec![],
                 },
                 OwnedCommandAndArgs {
                     command_path: PathBuf::from("/bin/bash"),
                     args: vec!["-c", "echo E"].into_iter().map_into().collect(),
+                    job_slots: vec![],
                 },
             ]
         );
@@ -381,10 +609,7 @@ mod test {
             ..Default::default()
         };
 
-        let parser = CommandLineArgsParser::new(
-            &command_line_args,
-            RegexProcessor::new(&command_line_args).unwrap(),
-        );
+        let parser = new_parser(command_line_args).unwrap();
 
         let result = collect_into_vec(parser);
 
@@ -397,6 +622,7 @@ mod test {
                         .into_iter()
                         .map_into()
                         .collect(),
+                    job_slots: vec![],
                 },
                 OwnedCommandAndArgs {
                     command_path: PathBuf::from("echo"),
@@ -404,6 +630,7 @@ mod test {
                         .into_iter()
                         .map_into()
                         .collect(),
+                    job_slots: vec![],
                 },
             ]
         );
@@ -429,10 +656,7 @@ mod test {
             ..Default::default()
         };
 
-        let parser = CommandLineArgsParser::new(
-            &command_line_args,
-            RegexProcessor::new(&command_line_args).unwrap(),
-        );
+        let parser = new_parser(command_line_args).unwrap();
 
         let result = collect_into_vec(parser);
 
@@ -445,6 +669,7 @@ mod test {
                         .into_iter()
                         .map_into()
                         .collect(),
+                    job_slots: vec![],
                 },
                 OwnedCommandAndArgs {
                     command_path: PathBuf::from("echo"),
@@ -452,8 +677,184 @@ mod test {
                         .into_iter()
                         .map_into()
                         .collect(),
+                    job_slots: vec![],
                 },
             ]
         );
     }
+
+    #[test]
+    fn test_parse_command_line_args_linked_groups() {
+        let command_line_args = CommandLineArgs {
+            shell: false,
+            command_and_initial_arguments: vec![
+                "echo", ":::", "A", "B", ":::+", "1", "2", ":::", "x", "y",
+            ]
+            .into_iter()
+            .map_into()
+            .collect(),
+            ..Default::default()
+        };
+
+        let parser = new_parser(command_line_args).unwrap();
+
+        let result = collect_into_vec(parser);
+
+        assert_eq!(
+            result
+                .into_iter()
+                .map(|cmd_and_args| cmd_and_args.args.join(OsStr::new(" ")))
+                .collect_vec(),
+            vec!["A 1 x", "A 1 y", "B 2 x", "B 2 y"]
+        );
+    }
+
+    #[test]
+    fn test_parse_command_line_args_linked_groups_mismatch() {
+        let command_and_initial_arguments: Vec<OsString> =
+            vec!["echo", ":::", "A", "B", "C", ":::+", "1", "2"]
+                .into_iter()
+                .map_into()
+                .collect();
+
+        let command_line_args = CommandLineArgs {
+            shell: false,
+            command_and_initial_arguments: command_and_initial_arguments.clone(),
+            ..Default::default()
+        };
+
+        let result = new_parser(command_line_args);
+
+        assert!(result.is_err());
+
+        let command_line_args = CommandLineArgs {
+            shell: false,
+            command_and_initial_arguments,
+            link_mismatch: LinkMismatch::Wrap,
+            ..Default::default()
+        };
+
+        let parser = new_parser(command_line_args).unwrap();
+
+        let result = collect_into_vec(parser);
+
+        assert_eq!(
+            result
+                .into_iter()
+                .map(|cmd_and_args| cmd_and_args.args.join(OsStr::new(" ")))
+                .collect_vec(),
+            vec!["A 1", "B 2", "C 1"]
+        );
+    }
+
+    #[test]
+    fn test_parse_command_line_args_lazy_product() {
+        let command_line_args = CommandLineArgs {
+            shell: false,
+            command_and_initial_arguments: vec![
+                "echo", ":::", "A", "B", ":::", "1", "2", ":::+", "x", ":::", "p", "q", "r",
+            ]
+            .into_iter()
+            .map_into()
+            .collect(),
+            link_mismatch: LinkMismatch::Wrap,
+            ..Default::default()
+        };
+
+        let mut parser = new_parser(command_line_args).unwrap();
+
+        assert_eq!(parser.remaining_argument_groups(), 12);
+
+        let (_, first_group) = parser.parse_next_argument_group(1).unwrap();
+
+        assert_eq!(first_group, vec!["A", "1", "x", "p"]);
+        assert_eq!(parser.remaining_argument_groups(), 11);
+
+        let result = collect_into_vec(parser);
+
+        assert_eq!(
+            result
+                .into_iter()
+                .map(|cmd_and_args| cmd_and_args.args.join(OsStr::new(" ")))
+                .collect_vec(),
+            vec![
+                "A 1 x q", "A 1 x r", "A 2 x p", "A 2 x q", "A 2 x r", "B 1 x p", "B 1 x q",
+                "B 1 x r", "B 2 x p", "B 2 x q", "B 2 x r",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_argument_groups_total_overflows() {
+        let linked_group_sets = (0..5)
+            .map(|_| LinkedGroupSet {
+                groups: vec![vec!["v".into()]],
+                rows: 1 << 20,
+            })
+            .collect_vec();
+
+        let result = ArgumentGroups::new(vec![], linked_group_sets);
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_parse_batch() {
+        let command_line_args = CommandLineArgs {
+            shell: false,
+            command_and_initial_arguments: vec![
+                "cp",
+                "-t",
+                "dest/{#}",
+                "src/{1}.{2}",
+                ":::",
+                "a",
+                "b",
+                ":::",
+                "x",
+            ]
+            .into_iter()
+            .map_into()
+            .collect(),
+            ..Default::default()
+        };
+
+        let mut parser = new_parser(command_line_args).unwrap();
+
+        let batch = std::iter::from_fn(|| parser.next_batch_input()).collect_vec();
+
+        assert_eq!(
+            parser.parse_batch(&batch, 3),
+            Some(OwnedCommandAndArgs {
+                command_path: PathBuf::from("cp"),
+                args: vec!["-t", "dest/3", "src/a.x", "src/b.x"]
+                    .into_iter()
+                    .map_into()
+                    .collect(),
+                job_slots: vec![],
+            })
+        );
+
+        let command_line_args = CommandLineArgs {
+            shell: false,
+            command_and_initial_arguments: vec!["echo", ":::", "a", "b", ":::", "x"]
+                .into_iter()
+                .map_into()
+                .collect(),
+            ..Default::default()
+        };
+
+        let mut parser = new_parser(command_line_args).unwrap();
+
+        let batch = std::iter::from_fn(|| parser.next_batch_input()).collect_vec();
+
+        assert_eq!(
+            parser.parse_batch(&batch, 1),
+            Some(OwnedCommandAndArgs {
+                command_path: PathBuf::from("echo"),
+                args: vec!["a", "x", "b", "x"].into_iter().map_into().collect(),
+                job_slots: vec![],
+            })
+        );
+    }
 }
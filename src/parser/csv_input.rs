@@ -0,0 +1,141 @@
+use anyhow::{bail, Context};
+
+use std::ffi::OsString;
+
+use super::replace;
+
+/// Column names from the header row of a --csv input.
+#[derive(Debug)]
+pub struct CsvHeader {
+    column_names: Vec<String>,
+    replace_mode: bool,
+}
+
+impl CsvHeader {
+    fn new(column_names: Vec<String>, command_template: &[OsString]) -> anyhow::Result<Self> {
+        for name in replace::explicit_column_names(command_template) {
+            if !column_names.iter().any(|column_name| column_name == name) {
+                bail!(
+                    "unknown CSV column {{col:{}}}, header columns are {:?}",
+                    name,
+                    column_names
+                );
+            }
+        }
+
+        let replace_mode = replace::contains_replacement_strings(command_template)
+            || replace::contains_column_names(command_template, &column_names);
+
+        Ok(Self {
+            column_names,
+            replace_mode,
+        })
+    }
+
+    pub fn column_names(&self) -> &[String] {
+        &self.column_names
+    }
+
+    /// Returns true if the command template refers to columns, otherwise the fields of
+    /// each record are appended to the command.
+    pub fn replace_mode(&self) -> bool {
+        self.replace_mode
+    }
+
+    fn check_fields(&self, fields: &[String]) -> anyhow::Result<()> {
+        if fields.len() != self.column_names.len() {
+            bail!(
+                "CSV record has {} fields but the header has {}",
+                fields.len(),
+                self.column_names.len()
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Parses one CSV record, which may span several lines.  Returns None for a blank line.
+fn parse_record(segment: &[u8]) -> anyhow::Result<Option<Vec<String>>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(segment);
+
+    let mut record = csv::StringRecord::new();
+
+    if !reader
+        .read_record(&mut record)
+        .context("error parsing CSV record")?
+    {
+        return Ok(None);
+    }
+
+    Ok(Some(record.iter().map(str::to_owned).collect()))
+}
+
+/// Parses the header row of a --csv input.
+pub fn parse_header(segment: &[u8], command_template: &[OsString]) -> anyhow::Result<CsvHeader> {
+    let column_names = parse_record(segment)?.context("CSV header row is empty")?;
+
+    CsvHeader::new(column_names, command_template)
+}
+
+/// Parses a record following the header row, returning None for a blank line.
+pub fn parse_fields(segment: &[u8], csv_header: &CsvHeader) -> anyhow::Result<Option<Vec<String>>> {
+    let Some(fields) = parse_record(segment)? else {
+        return Ok(None);
+    };
+
+    csv_header.check_fields(&fields)?;
+
+    Ok(Some(fields))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn template(args: &[&str]) -> Vec<OsString> {
+        args.iter().map(OsString::from).collect()
+    }
+
+    #[test]
+    fn test_parse_header() {
+        let csv_header = parse_header(b"file,mode", &template(&["cp", "{file}"])).unwrap();
+
+        assert_eq!(csv_header.column_names(), ["file", "mode"]);
+        assert!(csv_header.replace_mode());
+
+        let csv_header = parse_header(b"file,mode", &template(&["echo"])).unwrap();
+
+        assert!(!csv_header.replace_mode());
+
+        let error = parse_header(b"file,mode", &template(&["cp", "{col:name}"])).unwrap_err();
+
+        assert_eq!(
+            error.to_string(),
+            r#"unknown CSV column {col:name}, header columns are ["file", "mode"]"#
+        );
+
+        assert!(parse_header(b"", &template(&["echo"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_fields() {
+        let csv_header = parse_header(b"name,notes", &template(&["echo"])).unwrap();
+
+        assert_eq!(
+            parse_fields(b"a,\"one, \"\"two\"\"\nthree\"", &csv_header).unwrap(),
+            Some(vec!["a".to_owned(), "one, \"two\"\nthree".to_owned()])
+        );
+
+        assert_eq!(parse_fields(b"", &csv_header).unwrap(), None);
+
+        let error = parse_fields(b"a,b,c", &csv_header).unwrap_err();
+
+        assert_eq!(
+            error.to_string(),
+            "CSV record has 3 fields but the header has 2"
+        );
+    }
+}
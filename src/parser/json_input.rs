@@ -0,0 +1,198 @@
+use anyhow::{bail, Context};
+
+use serde::Deserialize;
+
+use serde_json::Value;
+
+use std::{borrow::Cow, collections::BTreeMap, time::Duration};
+
+use crate::common::JobOptions;
+
+/// Field of a --jsonl input object holding options for its job, removed before
+/// placeholders are replaced.
+const JOB_OPTIONS_FIELD: &str = "_job";
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct JobOptionsField {
+    /// Timeout seconds.
+    timeout: Option<f64>,
+    #[serde(default)]
+    env: BTreeMap<String, String>,
+}
+
+impl TryFrom<JobOptionsField> for JobOptions {
+    type Error = anyhow::Error;
+
+    fn try_from(field: JobOptionsField) -> anyhow::Result<Self> {
+        let timeout = field
+            .timeout
+            .map(Duration::try_from_secs_f64)
+            .transpose()
+            .context("invalid timeout")?;
+
+        Ok(Self {
+            timeout,
+            env: field.env.into_iter().collect(),
+        })
+    }
+}
+
+/// One parsed line of a --jsonl input.
+#[derive(Debug)]
+pub struct JsonLine {
+    pub value: Value,
+    pub job_options: JobOptions,
+}
+
+/// A --jsonl input line that is not a valid JSON object.
+#[derive(thiserror::Error, Debug)]
+#[error("{0:#}")]
+pub struct InvalidJsonError(anyhow::Error);
+
+/// The text of a --jsonl input line, which must be UTF-8 like all JSON.
+pub fn line_from_segment(segment: Vec<u8>) -> Result<String, InvalidJsonError> {
+    String::from_utf8(segment)
+        .context("invalid UTF-8")
+        .map_err(InvalidJsonError)
+}
+
+pub fn parse_line(input_line: &str) -> Result<JsonLine, InvalidJsonError> {
+    parse_object(input_line).map_err(InvalidJsonError)
+}
+
+fn parse_object(input_line: &str) -> anyhow::Result<JsonLine> {
+    let mut value: Value = serde_json::from_str(input_line).context("invalid JSON")?;
+
+    let Some(object) = value.as_object_mut() else {
+        bail!("JSON line is not an object");
+    };
+
+    let job_options = match object.remove(JOB_OPTIONS_FIELD) {
+        None => JobOptions::default(),
+        Some(field) => serde_json::from_value::<JobOptionsField>(field)
+            .map_err(anyhow::Error::from)
+            .and_then(JobOptions::try_from)
+            .with_context(|| format!("invalid {:?} field", JOB_OPTIONS_FIELD))?,
+    };
+
+    Ok(JsonLine { value, job_options })
+}
+
+enum PathSegment<'a> {
+    Key(&'a str),
+    Index(usize),
+}
+
+/// Parses a field path such as ".user.id" or ".files[0]".
+fn parse_path(path: &str) -> Option<Vec<PathSegment<'_>>> {
+    let mut rest = path.strip_prefix('.')?;
+    let mut segments = vec![];
+
+    loop {
+        let key_end = rest.find(['.', '[']).unwrap_or(rest.len());
+        let (key, after_key) = rest.split_at(key_end);
+        if !key.is_empty() {
+            segments.push(PathSegment::Key(key));
+        } else if !after_key.starts_with('[') {
+            return None;
+        }
+        rest = after_key;
+
+        while let Some(after_bracket) = rest.strip_prefix('[') {
+            let (index, after_index) = after_bracket.split_once(']')?;
+            segments.push(PathSegment::Index(index.parse().ok()?));
+            rest = after_index;
+        }
+
+        if rest.is_empty() {
+            break;
+        }
+        rest = rest.strip_prefix('.')?;
+    }
+
+    Some(segments)
+}
+
+/// Returns true if `path` is a field path such as ".user.id".
+pub fn is_path(path: &str) -> bool {
+    parse_path(path).is_some()
+}
+
+/// Returns the value at field `path` of `value`, None if `path` is not a field path.
+/// Missing fields and null are empty, strings are unquoted and other values are JSON.
+pub fn path_value<'v>(value: &'v Value, path: &str) -> Option<Cow<'v, str>> {
+    let mut value = Some(value);
+
+    for segment in parse_path(path)? {
+        value = value.and_then(|value| match segment {
+            PathSegment::Key(key) => value.get(key),
+            PathSegment::Index(index) => value.get(index),
+        });
+    }
+
+    Some(match value {
+        None | Some(Value::Null) => Cow::from(""),
+        Some(Value::String(s)) => Cow::from(s.as_str()),
+        Some(value) => Cow::from(value.to_string()),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_line() {
+        let json_line =
+            parse_line(r#"{"id": 1, "_job": {"timeout": 2.5, "env": {"B": "2", "A": "1"}}}"#)
+                .unwrap();
+
+        assert_eq!(json_line.value, serde_json::json!({"id": 1}));
+        assert_eq!(
+            json_line.job_options,
+            JobOptions {
+                timeout: Some(Duration::from_millis(2500)),
+                env: vec![
+                    ("A".to_owned(), "1".to_owned()),
+                    ("B".to_owned(), "2".to_owned())
+                ],
+            }
+        );
+
+        assert_eq!(
+            parse_line(r#"{"id": 1}"#).unwrap().job_options,
+            JobOptions::default()
+        );
+
+        assert!(parse_line(r#"{"id": "#).is_err());
+        assert!(parse_line("[1, 2]").is_err());
+        assert!(parse_line(r#"{"_job": {"timeout": -1}}"#).is_err());
+        assert!(parse_line(r#"{"_job": {"retries": 1}}"#).is_err());
+    }
+
+    #[test]
+    fn test_path_value() {
+        let value = serde_json::json!({
+            "user": {"id": 42, "name": "ann"},
+            "files": ["a.txt", "b.txt"],
+            "tags": {"x": [1, {"y": null}]},
+        });
+
+        assert_eq!(path_value(&value, ".user.id").unwrap(), "42");
+        assert_eq!(path_value(&value, ".user.name").unwrap(), "ann");
+        assert_eq!(path_value(&value, ".files[1]").unwrap(), "b.txt");
+        assert_eq!(
+            path_value(&value, ".files").unwrap(),
+            r#"["a.txt","b.txt"]"#
+        );
+        assert_eq!(path_value(&value, ".tags.x[1].y").unwrap(), "");
+        assert_eq!(path_value(&value, ".missing.field").unwrap(), "");
+        assert_eq!(path_value(&value, ".files[5]").unwrap(), "");
+
+        assert!(path_value(&value, ".").is_none());
+        assert!(path_value(&value, "user").is_none());
+        assert!(path_value(&value, ".files[x]").is_none());
+        assert!(path_value(&value, ".user..id").is_none());
+    }
+}
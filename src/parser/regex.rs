@@ -1,8 +1,10 @@
 use anyhow::Context;
 
-use std::borrow::Cow;
+use std::{borrow::Cow, ffi::OsStr};
 
-use crate::command_line_args::CommandLineArgs;
+use crate::{command_line_args::CommandLineArgs, common::os_string_from_bytes};
+
+use super::replace::{find_bytes, replace_bytes};
 
 #[derive(Clone)]
 pub struct RegexProcessor {
@@ -22,26 +24,32 @@ impl RegexProcessor {
         self.command_line_regex.is_some()
     }
 
-    pub fn process_string<'a>(&self, argument: &'a str, input_data: &str) -> Cow<'a, str> {
-        let argument = Cow::from(argument);
-
+    /// Expands the capture groups of `input_data` in `argument`.  The input is matched
+    /// as bytes, so it needn't be UTF-8.
+    pub fn process_string<'a>(
+        &self,
+        argument: &'a OsStr,
+        input_data: impl AsRef<OsStr>,
+    ) -> Cow<'a, OsStr> {
         match &self.command_line_regex {
-            None => argument,
-            Some(command_line_regex) => command_line_regex.expand(argument, input_data),
+            None => Cow::from(argument),
+            Some(command_line_regex) => {
+                command_line_regex.expand(argument, input_data.as_ref().as_encoded_bytes())
+            }
         }
     }
 }
 
 #[derive(Clone)]
 struct CommandLineRegex {
-    regex: regex::Regex,
+    regex: regex::bytes::Regex,
     numbered_group_match_keys: Vec<String>,
     named_group_to_match_key: Vec<(String, String)>,
 }
 
 impl CommandLineRegex {
     fn new(command_line_args_regex: &str) -> anyhow::Result<Self> {
-        let regex = regex::Regex::new(command_line_args_regex)
+        let regex = regex::bytes::Regex::new(command_line_args_regex)
             .context("CommandLineRegex::new: error creating regex")?;
 
         let capture_names = regex.capture_names();
@@ -67,17 +75,18 @@ impl CommandLineRegex {
         })
     }
 
-    fn expand<'a>(&self, argument: Cow<'a, str>, input_data: &str) -> Cow<'a, str> {
+    fn expand<'a>(&self, argument: &'a OsStr, input_data: &[u8]) -> Cow<'a, OsStr> {
         let captures = match self.regex.captures(input_data) {
-            None => return argument,
+            None => return Cow::from(argument),
             Some(captures) => captures,
         };
 
-        let mut argument = argument;
+        let mut expanded: Option<Vec<u8>> = None;
 
-        let mut update_argument = |match_key, match_value| {
-            if argument.contains(match_key) {
-                argument = Cow::from(argument.replace(match_key, match_value));
+        let mut update_argument = |match_key: &str, match_value: &[u8]| {
+            let current = expanded.as_deref().unwrap_or(argument.as_encoded_bytes());
+            if find_bytes(current, match_key.as_bytes()).is_some() {
+                expanded = Some(replace_bytes(current, match_key.as_bytes(), match_value));
             }
         };
 
@@ -86,22 +95,26 @@ impl CommandLineRegex {
             if let (Some(match_value), Some(match_key)) =
                 (match_option, self.numbered_group_match_keys.get(i))
             {
-                update_argument(match_key, match_value.as_str());
+                update_argument(match_key, match_value.as_bytes());
             }
         }
 
         // named capture groups
         for (group_name, match_key) in self.named_group_to_match_key.iter() {
             if let Some(match_value) = captures.name(group_name) {
-                update_argument(match_key, match_value.as_str());
+                update_argument(match_key, match_value.as_bytes());
             }
         }
 
-        argument
+        match expanded {
+            None => Cow::from(argument),
+            Some(expanded) => Cow::from(os_string_from_bytes(expanded)),
+        }
     }
 }
 
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
 mod test {
     use super::*;
 
@@ -116,7 +129,12 @@ mod test {
 
         assert_eq!(regex_processor.regex_mode(), false);
 
-        assert_eq!(regex_processor.process_string("{0}", "input line"), "{0}");
+        assert_eq!(
+            regex_processor
+                .process_string(OsStr::new("{0}"), "input line")
+                .into_owned(),
+            "{0}"
+        );
     }
 
     #[test]
@@ -131,7 +149,9 @@ mod test {
         assert_eq!(regex_processor.regex_mode(), true);
 
         assert_eq!(
-            regex_processor.process_string("{1} {2}", "hello,world"),
+            regex_processor
+                .process_string(OsStr::new("{1} {2}"), "hello,world")
+                .into_owned(),
             "hello world"
         );
     }
@@ -148,7 +168,9 @@ mod test {
         assert_eq!(regex_processor.regex_mode(), true);
 
         assert_eq!(
-            regex_processor.process_string("{arg1} {arg2}", "hello,world"),
+            regex_processor
+                .process_string(OsStr::new("{arg1} {arg2}"), "hello,world")
+                .into_owned(),
             "hello world"
         );
     }
@@ -165,10 +187,12 @@ mod test {
         assert_eq!(regex_processor.regex_mode(), true);
 
         assert_eq!(
-            regex_processor.process_string(
-                r#"{"id": 123, "$zero": "{0}", "one": "{1}", "two": "{2}"}"#,
-                "hello,world",
-            ),
+            regex_processor
+                .process_string(
+                    OsStr::new(r#"{"id": 123, "$zero": "{0}", "one": "{1}", "two": "{2}"}"#),
+                    "hello,world",
+                )
+                .into_owned(),
             r#"{"id": 123, "$zero": "hello,world", "one": "hello", "two": "world"}"#
         );
     }
@@ -185,10 +209,12 @@ mod test {
         assert_eq!(regex_processor.regex_mode(), true);
 
         assert_eq!(
-            regex_processor.process_string(
-                r#"{"id": 123, "$zero": "{0}", "one": "{arg1}", "two": "{arg2}"}"#,
-                "hello,world",
-            ),
+            regex_processor
+                .process_string(
+                    OsStr::new(r#"{"id": 123, "$zero": "{0}", "one": "{arg1}", "two": "{arg2}"}"#),
+                    "hello,world",
+                )
+                .into_owned(),
             r#"{"id": 123, "$zero": "hello,world", "one": "hello", "two": "world"}"#
         );
     }
@@ -205,11 +231,38 @@ mod test {
         assert_eq!(regex_processor.regex_mode(), true);
 
         assert_eq!(
-            regex_processor.process_string(r#"{arg2}${FOO}{arg1}$BAR${BAR}{arg2}"#, "hello,world"),
+            regex_processor
+                .process_string(
+                    OsStr::new(r#"{arg2}${FOO}{arg1}$BAR${BAR}{arg2}"#),
+                    "hello,world"
+                )
+                .into_owned(),
             r#"world${FOO}hello$BAR${BAR}world"#,
         );
     }
 
+    #[cfg(unix)]
+    #[test]
+    fn test_regex_non_utf8() {
+        use std::os::unix::ffi::{OsStrExt, OsStringExt};
+
+        let command_line_args = CommandLineArgs {
+            regex: Some("(?-u:(.*)),(.*)".to_string()),
+            ..Default::default()
+        };
+
+        let regex_processor = RegexProcessor::new(&command_line_args).unwrap();
+
+        let input = std::ffi::OsString::from_vec(b"caf\xe9,x".to_vec());
+
+        assert_eq!(
+            regex_processor
+                .process_string(OsStr::new("{2}/{1}"), &input)
+                .as_bytes(),
+            b"x/caf\xe9"
+        );
+    }
+
     #[test]
     fn test_regex_invalid() {
         let command_line_args = CommandLineArgs {
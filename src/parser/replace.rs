@@ -0,0 +1,539 @@
+use std::{borrow::Cow, ffi::OsStr};
+
+use crate::common::{os_string_from_bytes, JOB_SLOT};
+
+use super::json_input;
+
+const COLUMN_NAME_PREFIX: &str = "col:";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum PathModifier {
+    /// no modifier, the whole value
+    None,
+    /// "." value without extension
+    NoExtension,
+    /// "/" basename of value
+    Basename,
+    /// "//" dirname of value
+    Dirname,
+    /// "/." basename of value without extension
+    BasenameNoExtension,
+}
+
+impl PathModifier {
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "" => Some(Self::None),
+            "." => Some(Self::NoExtension),
+            "/" => Some(Self::Basename),
+            "//" => Some(Self::Dirname),
+            "/." => Some(Self::BasenameNoExtension),
+            _ => None,
+        }
+    }
+
+    fn apply(self, value: &[u8]) -> &[u8] {
+        match self {
+            Self::None => value,
+            Self::NoExtension => remove_extension(value),
+            Self::Basename => basename(value),
+            Self::Dirname => dirname(value),
+            Self::BasenameNoExtension => remove_extension(basename(value)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ReplacementString {
+    /// {} {.} {/} {//} {/.} all input values
+    Input(PathModifier),
+    /// {1} {1.} {1/} {1//} {1/.} the input value from one ::: group or input file, 1-based
+    Positional(usize, PathModifier),
+    /// {#} sequence number of the job
+    SequenceNumber,
+    /// {%} job slot number, replaced when the job starts
+    JobSlot,
+}
+
+/// Values substituted for GNU parallel style replacement strings.  Input values are
+/// substituted as bytes, so they needn't be UTF-8.
+pub struct ReplacementValues<'a, V> {
+    /// All input values joined with spaces.
+    pub input: &'a OsStr,
+    /// One value per ::: group, input file or column.
+    pub input_values: &'a [V],
+    /// Names of `input_values` for {name} and {col:name}, from a CSV header.
+    pub column_names: &'a [String],
+    /// Parsed --jsonl input line for field paths such as {.user.id}.
+    pub json: Option<&'a serde_json::Value>,
+    pub sequence_number: usize,
+}
+
+/// Returns the text between the '{' at the start of `s` and the next '}', None if it
+/// isn't UTF-8.
+fn braced(s: &[u8]) -> Option<&str> {
+    let inner = s.strip_prefix(b"{")?;
+    let end = inner.iter().position(|&b| b == b'}')?;
+
+    std::str::from_utf8(&inner[..end]).ok()
+}
+
+/// Parses the replacement string at the start of `s`, returning its length.
+fn parse_replacement_string(s: &[u8]) -> Option<(usize, ReplacementString)> {
+    let inner = braced(s)?;
+
+    let replacement_string = match inner {
+        "#" => ReplacementString::SequenceNumber,
+        "%" => ReplacementString::JobSlot,
+        _ => {
+            let digits_end = inner
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(inner.len());
+
+            let (digits, modifier) = inner.split_at(digits_end);
+
+            let modifier = PathModifier::parse(modifier)?;
+
+            if digits.is_empty() {
+                ReplacementString::Input(modifier)
+            } else {
+                let index = digits.parse().ok().filter(|index| *index > 0)?;
+                ReplacementString::Positional(index, modifier)
+            }
+        }
+    };
+
+    Some((inner.len() + 2, replacement_string))
+}
+
+/// Parses {name} or {col:name} at the start of `s`, returning its length and the name.
+fn parse_column_name(s: &[u8]) -> Option<(usize, &str)> {
+    let inner = braced(s)?;
+
+    let name = inner.strip_prefix(COLUMN_NAME_PREFIX).unwrap_or(inner);
+
+    Some((inner.len() + 2, name))
+}
+
+/// Returns the suffixes of `arg` starting with '{', where replacement strings may start.
+fn braces(arg: &[u8]) -> impl Iterator<Item = &[u8]> {
+    (0..arg.len())
+        .filter(|&i| arg[i] == b'{')
+        .map(|i| &arg[i..])
+}
+
+fn replacement_strings(arg: &OsStr) -> impl Iterator<Item = ReplacementString> + '_ {
+    braces(arg.as_encoded_bytes())
+        .filter_map(parse_replacement_string)
+        .map(|(_, replacement_string)| replacement_string)
+}
+
+/// Returns true if any argument contains a replacement string.
+pub fn contains_replacement_strings<S: AsRef<OsStr>>(args: &[S]) -> bool {
+    args.iter()
+        .any(|arg| replacement_strings(arg.as_ref()).next().is_some())
+}
+
+/// Returns true if any argument contains a positional replacement string such as {1}.
+pub fn contains_positional_replacement_strings<S: AsRef<OsStr>>(args: &[S]) -> bool {
+    args.iter().any(|arg| {
+        replacement_strings(arg.as_ref()).any(|replacement_string| {
+            matches!(replacement_string, ReplacementString::Positional(..))
+        })
+    })
+}
+
+/// Returns the largest positional replacement string index used by any argument.
+pub fn max_positional_index<S: AsRef<OsStr>>(args: &[S]) -> Option<usize> {
+    args.iter()
+        .flat_map(|arg| replacement_strings(arg.as_ref()))
+        .filter_map(|replacement_string| match replacement_string {
+            ReplacementString::Positional(index, _) => Some(index),
+            _ => None,
+        })
+        .max()
+}
+
+/// Returns the names used as {col:name} in any argument.
+pub fn explicit_column_names<S: AsRef<OsStr>>(args: &[S]) -> Vec<&str> {
+    args.iter()
+        .flat_map(|arg| {
+            braces(arg.as_ref().as_encoded_bytes()).filter_map(|rest| {
+                if parse_replacement_string(rest).is_some() {
+                    return None;
+                }
+                let (len, name) = parse_column_name(rest)?;
+                rest[1..len - 1]
+                    .starts_with(COLUMN_NAME_PREFIX.as_bytes())
+                    .then_some(name)
+            })
+        })
+        .collect()
+}
+
+/// Returns true if any argument contains {name} or {col:name} for one of `column_names`.
+pub fn contains_column_names<S: AsRef<OsStr>>(args: &[S], column_names: &[String]) -> bool {
+    args.iter().any(|arg| {
+        braces(arg.as_ref().as_encoded_bytes()).any(|rest| {
+            parse_replacement_string(rest).is_none()
+                && parse_column_name(rest)
+                    .is_some_and(|(_, name)| column_names.iter().any(|n| n == name))
+        })
+    })
+}
+
+/// Returns true if any argument contains a JSON field path such as {.user.id}.
+pub fn contains_json_paths<S: AsRef<OsStr>>(args: &[S]) -> bool {
+    args.iter().any(|arg| {
+        braces(arg.as_ref().as_encoded_bytes()).any(|rest| {
+            parse_replacement_string(rest).is_none()
+                && parse_column_name(rest).is_some_and(|(_, path)| json_input::is_path(path))
+        })
+    })
+}
+
+/// Returns true if `arg` refers to the values of one input: {} or {1} with any path
+/// modifier, one of `column_names`, or a JSON field path if `json_paths` is true.
+pub fn refers_to_input(arg: &OsStr, column_names: &[String], json_paths: bool) -> bool {
+    braces(arg.as_encoded_bytes()).any(|rest| match parse_replacement_string(rest) {
+        Some((_, ReplacementString::Input(_) | ReplacementString::Positional(..))) => true,
+        Some(_) => false,
+        None => parse_column_name(rest).is_some_and(|(_, name)| {
+            column_names.iter().any(|n| n == name) || (json_paths && json_input::is_path(name))
+        }),
+    })
+}
+
+/// Returns true if any argument contains the job slot replacement string "{%}".
+pub fn contains_job_slot<S: AsRef<OsStr>>(args: &[S]) -> bool {
+    args.iter()
+        .any(|arg| find_bytes(arg.as_ref().as_encoded_bytes(), JOB_SLOT.as_bytes()).is_some())
+}
+
+/// Replaces every replacement string in `arg` except {%}, which is left because the
+/// job slot is only known when the job starts.  The byte offsets of the {%} left in the
+/// result are pushed to `job_slot_offsets`, so "{%}" in input values isn't mistaken
+/// for it.  Positional replacement strings past the last input value, unknown column
+/// names and invalid JSON field paths are left as is.
+pub fn replace<'a, V: AsRef<OsStr>>(
+    arg: &'a OsStr,
+    values: &ReplacementValues<V>,
+    job_slot_offsets: &mut Vec<usize>,
+) -> Cow<'a, OsStr> {
+    let arg_bytes = arg.as_encoded_bytes();
+
+    if !arg_bytes.contains(&b'{') {
+        return Cow::from(arg);
+    }
+
+    let mut result = Vec::with_capacity(arg_bytes.len());
+    let mut rest = arg_bytes;
+
+    while let Some(i) = rest.iter().position(|&b| b == b'{') {
+        result.extend_from_slice(&rest[..i]);
+        rest = &rest[i..];
+
+        let replacement = match parse_replacement_string(rest) {
+            Some((len, replacement_string)) => {
+                let value = match replacement_string {
+                    ReplacementString::Input(modifier) => {
+                        Some(Cow::from(modifier.apply(values.input.as_encoded_bytes())))
+                    }
+                    ReplacementString::Positional(index, modifier) => values
+                        .input_values
+                        .get(index - 1)
+                        .map(|value| Cow::from(modifier.apply(value.as_ref().as_encoded_bytes()))),
+                    ReplacementString::SequenceNumber => {
+                        Some(Cow::from(values.sequence_number.to_string().into_bytes()))
+                    }
+                    ReplacementString::JobSlot => {
+                        job_slot_offsets.push(result.len());
+                        None
+                    }
+                };
+                value.map(|value| (len, value))
+            }
+            None => parse_column_name(rest).and_then(|(len, name)| {
+                if let Some(json) = values.json {
+                    return json_input::path_value(json, name)
+                        .map(|value| (len, Cow::from(value.into_owned().into_bytes())));
+                }
+                let index = values.column_names.iter().position(|n| n == name)?;
+                let value = values.input_values.get(index)?;
+                Some((len, Cow::from(value.as_ref().as_encoded_bytes())))
+            }),
+        };
+
+        match replacement {
+            None => {
+                result.push(b'{');
+                rest = &rest[1..];
+            }
+            Some((len, value)) => {
+                result.extend_from_slice(&value);
+                rest = &rest[len..];
+            }
+        }
+    }
+
+    result.extend_from_slice(rest);
+
+    Cow::from(os_string_from_bytes(result))
+}
+
+/// Returns the position of the first `needle` in `haystack`.
+pub fn find_bytes(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+/// Replaces every `from` in `bytes` with `to`.
+pub fn replace_bytes(bytes: &[u8], from: &[u8], to: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(bytes.len());
+    let mut rest = bytes;
+
+    while let Some(i) = find_bytes(rest, from) {
+        result.extend_from_slice(&rest[..i]);
+        result.extend_from_slice(to);
+        rest = &rest[i + from.len()..];
+    }
+
+    result.extend_from_slice(rest);
+
+    result
+}
+
+fn basename(path: &[u8]) -> &[u8] {
+    path.rsplit(|&b| b == b'/').next().unwrap_or(path)
+}
+
+fn dirname(path: &[u8]) -> &[u8] {
+    match path.iter().rposition(|&b| b == b'/') {
+        None => b".",
+        Some(0) => b"/",
+        Some(i) => &path[..i],
+    }
+}
+
+/// Removes the last ".extension" from the final path component, like GNU parallel.
+fn remove_extension(path: &[u8]) -> &[u8] {
+    match path.iter().rposition(|&b| b == b'.') {
+        Some(i) if !path[i..].contains(&b'/') => &path[..i],
+        _ => path,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::ffi::OsString;
+
+    fn replace_all(arg: &str, input: &str) -> OsString {
+        replace(
+            OsStr::new(arg),
+            &ReplacementValues {
+                input: OsStr::new(input),
+                input_values: &[input.to_owned()],
+                column_names: &[],
+                json: None,
+                sequence_number: 7,
+            },
+            &mut vec![],
+        )
+        .into_owned()
+    }
+
+    #[test]
+    fn test_replace() {
+        let input = "dir/sub.d/file.tar.gz";
+
+        assert_eq!(replace_all("{}", input), "dir/sub.d/file.tar.gz");
+        assert_eq!(replace_all("{.}", input), "dir/sub.d/file.tar");
+        assert_eq!(replace_all("{/}", input), "file.tar.gz");
+        assert_eq!(replace_all("{//}", input), "dir/sub.d");
+        assert_eq!(replace_all("{/.}", input), "file.tar");
+        assert_eq!(replace_all("{#}", input), "7");
+        assert_eq!(replace_all("{%}", input), "{%}");
+        assert_eq!(replace_all("out/{/.}.{#}.txt", input), "out/file.tar.7.txt");
+        assert_eq!(
+            replace_all(r#"{"a": "{}"} {x} {"#, "v"),
+            r#"{"a": "v"} {x} {"#
+        );
+    }
+
+    #[test]
+    fn test_replace_positional() {
+        let input_values = vec!["a/b.txt".to_owned(), "fast".to_owned()];
+
+        let input = input_values.join(" ");
+
+        let replacement_values = ReplacementValues {
+            input: OsStr::new(&input),
+            input_values: &input_values,
+            column_names: &[],
+            json: None,
+            sequence_number: 1,
+        };
+
+        let replace_positional =
+            |arg| replace(OsStr::new(arg), &replacement_values, &mut vec![]).into_owned();
+
+        assert_eq!(
+            replace_positional("--in {1} --mode {2}"),
+            "--in a/b.txt --mode fast"
+        );
+        assert_eq!(replace_positional("{1.} {1/} {1//} {1/.}"), "a/b b.txt a b");
+        assert_eq!(replace_positional("{}"), "a/b.txt fast");
+        assert_eq!(replace_positional("{3} {0} {1x}"), "{3} {0} {1x}");
+    }
+
+    #[test]
+    fn test_path_edge_cases() {
+        assert_eq!(replace_all("{//}", "file.txt"), ".");
+        assert_eq!(replace_all("{//}", "/file.txt"), "/");
+        assert_eq!(replace_all("{.}", "dir.d/file"), "dir.d/file");
+        assert_eq!(replace_all("{/}", "file"), "file");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_replace_non_utf8() {
+        use std::os::unix::ffi::{OsStrExt, OsStringExt};
+
+        let input = OsString::from_vec(b"dir/caf\xe9.txt".to_vec());
+
+        let replacement_values = ReplacementValues {
+            input: &input,
+            input_values: std::slice::from_ref(&input),
+            column_names: &[],
+            json: None,
+            sequence_number: 1,
+        };
+
+        let replace_bytes =
+            |arg| replace(OsStr::new(arg), &replacement_values, &mut vec![]).into_owned();
+
+        assert_eq!(
+            replace_bytes("{/.} {1.}.gz").as_bytes(),
+            b"caf\xe9 dir/caf\xe9.gz"
+        );
+    }
+
+    #[test]
+    fn test_contains_replacement_strings() {
+        assert!(contains_replacement_strings(&["echo", "{}"]));
+        assert!(contains_replacement_strings(&["gzip", "-c", "{/.}.gz"]));
+        assert!(contains_replacement_strings(&["echo", "{%}"]));
+        assert!(contains_replacement_strings(&["echo", "{2/}"]));
+        assert!(!contains_replacement_strings(&["echo", "{x}", "${HOME}"]));
+        assert!(contains_positional_replacement_strings(&[
+            "cp", "{1}", "{2//}"
+        ]));
+        assert!(!contains_positional_replacement_strings(&["echo", "{}"]));
+        assert_eq!(
+            max_positional_index(&["cp", "{2/}", "{10}", "{1}"]),
+            Some(10)
+        );
+        assert_eq!(max_positional_index(&["echo", "{}"]), None);
+        assert!(contains_job_slot(&["echo", "slot={%}"]));
+        assert!(!contains_job_slot(&["echo", "{}"]));
+    }
+
+    #[test]
+    fn test_replace_job_slot_offsets() {
+        let input = OsStr::new("a{%}");
+
+        let replacement_values = ReplacementValues {
+            input,
+            input_values: &[input],
+            column_names: &[],
+            json: None,
+            sequence_number: 1,
+        };
+
+        let mut job_slot_offsets = vec![];
+
+        assert_eq!(
+            replace(
+                OsStr::new("{%}:{}:{%}"),
+                &replacement_values,
+                &mut job_slot_offsets
+            )
+            .into_owned(),
+            "{%}:a{%}:{%}"
+        );
+        assert_eq!(job_slot_offsets, vec![0, 9]);
+
+        let mut job_slot_offsets = vec![];
+
+        replace(OsStr::new("{}"), &replacement_values, &mut job_slot_offsets);
+        assert!(job_slot_offsets.is_empty());
+    }
+
+    #[test]
+    fn test_replace_column_names() {
+        let input_values = vec!["a.txt".to_owned(), "fast".to_owned(), "x".to_owned()];
+        let column_names = vec!["file".to_owned(), "mode".to_owned(), "1".to_owned()];
+
+        let input = input_values.join(" ");
+
+        let replacement_values = ReplacementValues {
+            input: OsStr::new(&input),
+            input_values: &input_values,
+            column_names: &column_names,
+            json: None,
+            sequence_number: 1,
+        };
+
+        let replace_named =
+            |arg| replace(OsStr::new(arg), &replacement_values, &mut vec![]).into_owned();
+
+        assert_eq!(replace_named("{file.} {col:mode}"), "{file.} fast");
+        assert_eq!(replace_named("{file}:{1}:{col:1}"), "a.txt:a.txt:x");
+        assert_eq!(replace_named("{other} {col:other}"), "{other} {col:other}");
+
+        assert!(contains_column_names(&["echo", "{file}"], &column_names));
+        assert!(!contains_column_names(&["echo", "{other}"], &column_names));
+        assert!(refers_to_input(OsStr::new("{mode}"), &column_names, false));
+        assert!(refers_to_input(OsStr::new("out/{1/.}"), &[], false));
+        assert!(!refers_to_input(
+            OsStr::new("{#}-{%}-{other}"),
+            &column_names,
+            false
+        ));
+        assert_eq!(
+            explicit_column_names(&["cp", "{col:file}", "{mode}", "{col:x}/{2}"]),
+            vec!["file", "x"]
+        );
+    }
+
+    #[test]
+    fn test_replace_json_paths() {
+        let input = r#"{"user": {"id": 7}, "files": ["a.txt"]}"#;
+        let json = serde_json::from_str(input).unwrap();
+
+        let replacement_values = ReplacementValues {
+            input: OsStr::new(input),
+            input_values: &[input.to_owned()],
+            column_names: &[],
+            json: Some(&json),
+            sequence_number: 1,
+        };
+
+        let replace_json =
+            |arg| replace(OsStr::new(arg), &replacement_values, &mut vec![]).into_owned();
+
+        assert_eq!(
+            replace_json("--user={.user.id} {.files[0]} {.missing}"),
+            "--user=7 a.txt "
+        );
+        assert_eq!(replace_json("{.files[0]/.} {x}"), "{.files[0]/.} {x}");
+
+        assert!(contains_json_paths(&["echo", "{.user.id}"]));
+        assert!(refers_to_input(OsStr::new("--id={.user.id}"), &[], true));
+        assert!(!refers_to_input(OsStr::new("--id={.user.id}"), &[], false));
+        assert!(!contains_json_paths(&["echo", "{.}", "{x}"]));
+    }
+}
@@ -1,29 +1,82 @@
 use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
     process::{Child, Command},
+    sync::watch,
     time::Duration,
 };
 
+use tracing::{debug, warn};
+
 use std::{
     ffi::OsStr,
-    process::{Output, Stdio},
+    future::Future,
+    process::{ExitStatus, Stdio},
+    sync::Arc,
+};
+
+use crate::{
+    command_line_args::{
+        ChildSignal, CommandLineArgs, DiscardOutput, TimeoutSignalSequence, TimeoutSignalStep,
+    },
+    common::JobOptions,
+    output::{OutputBuffer, OutputSender, OutputStream},
 };
 
-use crate::command_line_args::{CommandLineArgs, DiscardOutput};
+const LINE_BUFFER_READ_SIZE: usize = 8 * 1024;
+
+/// How long to wait for the rest of the output of a child after its timeout.
+const OUTPUT_AFTER_TIMEOUT_LIMIT: Duration = Duration::from_secs(1);
 
 #[derive(thiserror::Error, Debug)]
 pub enum ChildProcessExecutionError {
+    /// Contains the exit status of the child process after the timeout signals were sent,
+    /// and the output it wrote before being terminated.
     #[error("timeout: {0}")]
-    Timeout(#[from] tokio::time::error::Elapsed),
+    Timeout(
+        tokio::time::error::Elapsed,
+        Option<ExitStatus>,
+        Option<Box<ChildProcessOutput>>,
+    ),
 
     #[error("i/o error: {0}")]
     IOError(#[from] std::io::Error),
 }
 
+#[derive(Debug)]
+pub struct ChildProcessOutput {
+    pub stdout: OutputBuffer,
+    pub stderr: OutputBuffer,
+    /// Bytes written to stdout, including lines already forwarded in line buffer mode.
+    pub stdout_bytes: usize,
+    /// Bytes written to stderr, including lines already forwarded in line buffer mode.
+    pub stderr_bytes: usize,
+}
+
+impl ChildProcessOutput {
+    fn new(stdout: OutputBuffer, stderr: OutputBuffer) -> Self {
+        Self {
+            stdout_bytes: stdout.len(),
+            stderr_bytes: stderr.len(),
+            stdout,
+            stderr,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct ChildProcess {
     child: Child,
+    /// Process group of the child, kept because the child has no id once reaped while
+    /// its descendants may still run.  None once the child completed by itself.
+    process_group: Option<u32>,
+    /// Written to the stdin of the child, which is closed afterwards.
+    stdin: Option<Arc<[u8]>>,
     discard_all_output: bool,
+    line_buffer: bool,
+    output_memory_limit: usize,
     timeout: Option<Duration>,
+    timeout_signals: Arc<TimeoutSignalSequence>,
+    forwarded_signal_receiver: watch::Receiver<Option<ChildSignal>>,
 }
 
 impl ChildProcess {
@@ -31,30 +84,295 @@ impl ChildProcess {
         self.child.id()
     }
 
-    async fn await_output(mut self) -> Result<Output, ChildProcessExecutionError> {
-        let output = if self.discard_all_output {
-            Output {
-                status: self.child.wait().await?,
-                stdout: vec![],
-                stderr: vec![],
+    /// Reads the output of the child while writing its stdin.  The returned future doesn't
+    /// borrow the child, so output read before a timeout is kept while it is terminated.
+    fn read_output<'a>(
+        &mut self,
+        output_sender: &'a OutputSender,
+    ) -> impl Future<Output = std::io::Result<ChildProcessOutput>> + 'a {
+        let stdout = self.child.stdout.take();
+        let stderr = self.child.stderr.take();
+
+        // Stdin is written while output is read, so a child filling its output pipe
+        // before reading all of its input can't deadlock.
+        let stdin = write_stdin(self.child.stdin.take(), self.stdin.take());
+
+        let line_buffer = self.line_buffer;
+        let discard_all_output = self.discard_all_output;
+        let output_memory_limit = self.output_memory_limit;
+
+        async move {
+            let output = if line_buffer {
+                let (stdin_result, stdout_result, stderr_result) = tokio::join!(
+                    stdin,
+                    forward_lines(stdout, OutputStream::Stdout, output_sender),
+                    forward_lines(stderr, OutputStream::Stderr, output_sender),
+                );
+                stdin_result?;
+
+                ChildProcessOutput {
+                    stdout: OutputBuffer::default(),
+                    stderr: OutputBuffer::default(),
+                    stdout_bytes: stdout_result?,
+                    stderr_bytes: stderr_result?,
+                }
+            } else if discard_all_output {
+                stdin.await?;
+
+                ChildProcessOutput::new(OutputBuffer::default(), OutputBuffer::default())
+            } else {
+                let (stdin_result, stdout_result, stderr_result) = tokio::join!(
+                    stdin,
+                    OutputBuffer::read_from(stdout, output_memory_limit),
+                    OutputBuffer::read_from(stderr, output_memory_limit),
+                );
+                stdin_result?;
+
+                ChildProcessOutput::new(stdout_result?, stderr_result?)
+            };
+
+            Ok(output)
+        }
+    }
+
+    /// In line buffer mode stdout and stderr are forwarded to `output_sender` as complete
+    /// lines while the child is running, and the returned output has empty buffers.
+    /// Signals received by rust-parallel are forwarded to the process group of the child
+    /// until it completes.
+    pub async fn await_completion(
+        mut self,
+        output_sender: &OutputSender,
+    ) -> Result<(ExitStatus, ChildProcessOutput), ChildProcessExecutionError> {
+        let timeout = self.timeout;
+        let process_group = self.process_group;
+        let forwarded_signal_receiver = self.forwarded_signal_receiver.clone();
+
+        let output = self.read_output(output_sender);
+        tokio::pin!(output);
+
+        // Set once all output is read, so output read before a timeout isn't lost.
+        let mut output_result = None;
+
+        let completion = forward_signals_until(
+            async {
+                let (status, ()) = tokio::join!(self.child.wait(), async {
+                    output_result = Some(output.as_mut().await);
+                });
+                status
+            },
+            process_group,
+            forwarded_signal_receiver,
+        );
+
+        let result = match timeout {
+            None => Ok(completion.await),
+            Some(timeout) => tokio::time::timeout(timeout, completion).await,
+        };
+
+        let elapsed = match result {
+            Ok(status) => {
+                let status = status?;
+                let output = match output_result {
+                    Some(output_result) => output_result?,
+                    None => output.await?,
+                };
+                self.process_group = None;
+                return Ok((status, output));
             }
-        } else {
-            self.child.wait_with_output().await?
+            Err(elapsed) => elapsed,
         };
 
-        Ok(output)
+        let exit_status = self.terminate().await;
+
+        // The pipes close once the process group is killed, unless a process that left
+        // the group still holds them.
+        let output = match output_result {
+            Some(output_result) => output_result.ok(),
+            None => tokio::time::timeout(OUTPUT_AFTER_TIMEOUT_LIMIT, output)
+                .await
+                .ok()
+                .and_then(Result::ok),
+        };
+
+        Err(ChildProcessExecutionError::Timeout(
+            elapsed,
+            exit_status,
+            output.map(Box::new),
+        ))
+    }
+
+    /// Runs the timeout signal sequence against the process group of the child,
+    /// then kills anything left in the group.  The sequence stops early once the child
+    /// exits, but descendants that outlive it are still killed.
+    async fn terminate(&mut self) -> Option<ExitStatus> {
+        let timeout_signals = Arc::clone(&self.timeout_signals);
+
+        let mut exit_status = None;
+
+        for step in &timeout_signals.0 {
+            match *step {
+                TimeoutSignalStep::Signal(signal) => self.signal(signal),
+                TimeoutSignalStep::Wait(duration) => {
+                    if let Ok(result) = tokio::time::timeout(duration, self.child.wait()).await {
+                        exit_status = Some(result.ok());
+                        break;
+                    }
+                }
+            }
+        }
+
+        self.signal(ChildSignal::Kill);
+        self.process_group = None;
+
+        match exit_status {
+            Some(exit_status) => exit_status,
+            None => self.child.wait().await.ok(),
+        }
+    }
+
+    #[cfg(unix)]
+    fn signal(&mut self, signal: ChildSignal) {
+        signal_process_group(self.process_group, signal);
+    }
+
+    #[cfg(not(unix))]
+    fn signal(&mut self, _signal: ChildSignal) {
+        if self.child.id().is_none() {
+            return;
+        }
+
+        if let Err(e) = self.child.start_kill() {
+            warn!("kill error: {}", e);
+        }
+    }
+}
+
+impl Drop for ChildProcess {
+    /// Kills the whole process group if the child is dropped before completion,
+    /// e.g. when it is killed because of the halt policy.
+    fn drop(&mut self) {
+        self.signal(ChildSignal::Kill);
+    }
+}
+
+#[cfg(unix)]
+fn nix_signal(signal: ChildSignal) -> nix::sys::signal::Signal {
+    use nix::sys::signal::Signal;
+
+    match signal {
+        ChildSignal::Hup => Signal::SIGHUP,
+        ChildSignal::Int => Signal::SIGINT,
+        ChildSignal::Quit => Signal::SIGQUIT,
+        ChildSignal::Kill => Signal::SIGKILL,
+        ChildSignal::Usr1 => Signal::SIGUSR1,
+        ChildSignal::Usr2 => Signal::SIGUSR2,
+        ChildSignal::Term => Signal::SIGTERM,
+    }
+}
+
+#[cfg(unix)]
+fn signal_process_group(pid: Option<u32>, signal: ChildSignal) {
+    use nix::{sys::signal::killpg, unistd::Pid};
+
+    let Some(pid) = pid.and_then(|pid| i32::try_from(pid).ok()) else {
+        return;
+    };
+
+    let signal = nix_signal(signal);
+
+    debug!("sending {} to process group {}", signal, pid);
+
+    if let Err(e) = killpg(Pid::from_raw(pid), signal) {
+        // ESRCH means every process in the group already exited.
+        if e != nix::errno::Errno::ESRCH {
+            warn!("killpg error process group {}: {}", pid, e);
+        }
+    }
+}
+
+/// Child processes share the console on Windows, so console control events already reach them.
+#[cfg(not(unix))]
+fn signal_process_group(_pid: Option<u32>, _signal: ChildSignal) {}
+
+/// Exit code of a process terminated by `signal`, following the shell convention of 128 + signal number.
+#[cfg(unix)]
+pub fn signal_exit_code(signal: ChildSignal) -> i32 {
+    128 + nix_signal(signal) as i32
+}
+
+#[cfg(not(unix))]
+pub fn signal_exit_code(_signal: ChildSignal) -> i32 {
+    1
+}
+
+/// Forwards signals received by rust-parallel to `process_group` until `future` completes.
+async fn forward_signals_until<T>(
+    future: impl Future<Output = T>,
+    process_group: Option<u32>,
+    mut forwarded_signal_receiver: watch::Receiver<Option<ChildSignal>>,
+) -> T {
+    tokio::pin!(future);
+
+    loop {
+        tokio::select! {
+            result = &mut future => return result,
+            Ok(()) = forwarded_signal_receiver.changed() => {
+                let signal = *forwarded_signal_receiver.borrow_and_update();
+                if let Some(signal) = signal {
+                    signal_process_group(process_group, signal);
+                }
+            }
+        }
+    }
+}
+
+/// Writes `bytes` to `writer` and closes it.  A child exiting without reading all of
+/// its input is not an error.
+async fn write_stdin(
+    writer: Option<impl AsyncWrite + Unpin>,
+    bytes: Option<Arc<[u8]>>,
+) -> std::io::Result<()> {
+    let (Some(mut writer), Some(bytes)) = (writer, bytes) else {
+        return Ok(());
+    };
+
+    match writer.write_all(&bytes).await {
+        Err(e) if e.kind() == std::io::ErrorKind::BrokenPipe => Ok(()),
+        result => result,
     }
+}
+
+async fn forward_lines(
+    reader: Option<impl AsyncRead + Unpin>,
+    stream: OutputStream,
+    output_sender: &OutputSender,
+) -> std::io::Result<usize> {
+    let Some(mut reader) = reader else {
+        return Ok(0);
+    };
 
-    pub async fn await_completion(self) -> Result<Output, ChildProcessExecutionError> {
-        match self.timeout {
-            None => self.await_output().await,
-            Some(timeout) => {
-                let result = tokio::time::timeout(timeout, self.await_output()).await?;
+    let mut read_buffer = vec![0u8; LINE_BUFFER_READ_SIZE];
+    let mut pending = vec![];
+    let mut total_bytes = 0;
 
-                let output = result?;
+    loop {
+        let bytes_read = reader.read(&mut read_buffer).await?;
 
-                Ok(output)
+        if bytes_read == 0 {
+            if !pending.is_empty() {
+                output_sender.send_lines(stream, pending).await;
             }
+            return Ok(total_bytes);
+        }
+
+        total_bytes += bytes_read;
+
+        pending.extend_from_slice(&read_buffer[..bytes_read]);
+
+        if let Some(last_newline) = pending.iter().rposition(|b| *b == b'\n') {
+            let partial_line = pending.split_off(last_newline + 1);
+            let lines = std::mem::replace(&mut pending, partial_line);
+            output_sender.send_lines(stream, lines).await;
         }
     }
 }
@@ -63,11 +381,18 @@ impl ChildProcess {
 pub struct ChildProcessFactory {
     discard_stdout: bool,
     discard_stderr: bool,
+    line_buffer: bool,
+    output_memory_limit: usize,
     timeout: Option<Duration>,
+    timeout_signals: Arc<TimeoutSignalSequence>,
+    forwarded_signal_receiver: watch::Receiver<Option<ChildSignal>>,
 }
 
 impl ChildProcessFactory {
-    pub fn new(command_line_args: &CommandLineArgs) -> Self {
+    pub fn new(
+        command_line_args: &CommandLineArgs,
+        forwarded_signal_receiver: watch::Receiver<Option<ChildSignal>>,
+    ) -> Self {
         Self {
             discard_stdout: matches!(
                 command_line_args.discard_output,
@@ -77,9 +402,13 @@ impl ChildProcessFactory {
                 command_line_args.discard_output,
                 Some(DiscardOutput::All) | Some(DiscardOutput::Stderr)
             ),
+            line_buffer: command_line_args.line_buffer,
+            output_memory_limit: command_line_args.output_memory_limit,
             timeout: command_line_args
                 .timeout_seconds
                 .map(Duration::from_secs_f64),
+            timeout_signals: Arc::new(command_line_args.timeout_signals.clone()),
+            forwarded_signal_receiver,
         }
     }
 
@@ -103,24 +432,51 @@ impl ChildProcessFactory {
         self.discard_stdout && self.discard_stderr
     }
 
-    pub async fn spawn<C, AI, A>(self, command: C, args: AI) -> std::io::Result<ChildProcess>
+    pub async fn spawn<C, AI, A>(
+        self,
+        command: C,
+        args: AI,
+        job_options: &JobOptions,
+        stdin: Option<Arc<[u8]>>,
+    ) -> std::io::Result<ChildProcess>
     where
         C: AsRef<OsStr>,
         AI: IntoIterator<Item = A>,
         A: AsRef<OsStr>,
     {
-        let child = Command::new(command)
+        let mut std_command = std::process::Command::new(command);
+
+        // Run each child in its own process group so signals reach all of its descendants.
+        #[cfg(unix)]
+        std::os::unix::process::CommandExt::process_group(&mut std_command, 0);
+
+        let mut command = Command::from(std_command);
+
+        command
             .args(args)
-            .stdin(Stdio::null())
+            .envs(job_options.env.iter().map(|(key, value)| (key, value)))
+            .stdin(if stdin.is_some() {
+                Stdio::piped()
+            } else {
+                Stdio::null()
+            })
             .stdout(self.stdout())
             .stderr(self.stderr())
-            .kill_on_drop(self.timeout.is_some())
-            .spawn()?;
+            // ChildProcess is only dropped before completion on halt.
+            .kill_on_drop(true);
+
+        let child = command.spawn()?;
 
         Ok(ChildProcess {
+            process_group: child.id(),
             child,
+            stdin,
             discard_all_output: self.discard_all_output(),
-            timeout: self.timeout,
+            line_buffer: self.line_buffer,
+            output_memory_limit: self.output_memory_limit,
+            timeout: job_options.timeout.or(self.timeout),
+            timeout_signals: self.timeout_signals,
+            forwarded_signal_receiver: self.forwarded_signal_receiver,
         })
     }
 }
@@ -0,0 +1,274 @@
+use anyhow::Context;
+
+use serde::Serialize;
+
+use tokio::{
+    fs::File,
+    io::{AsyncWriteExt, BufWriter},
+    sync::Mutex,
+};
+
+use tracing::warn;
+
+use std::{
+    ffi::OsString,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    command::CommandResult,
+    command_line_args::{CommandLineArgs, ResultsIndexFormat, ResultsLayout},
+    common::{epoch_seconds, OwnedCommandAndArgs},
+    input::InputLineNumber,
+    output::OutputBuffer,
+    process::ChildProcessOutput,
+};
+
+/// Longest directory name made from an input value, within the usual file name limit of 255 bytes.
+const MAX_DIR_NAME_BYTES: usize = 200;
+
+const CSV_HEADER: &str =
+    "seq,input,start_time,runtime,status,exit_code,signal,stdout_bytes,stderr_bytes,dir,command\n";
+
+#[derive(Serialize)]
+struct IndexEntry<'a> {
+    seq: usize,
+    input: String,
+    start_time: f64,
+    runtime: f64,
+    status: &'a str,
+    exit_code: Option<i32>,
+    signal: Option<i32>,
+    stdout_bytes: usize,
+    stderr_bytes: usize,
+    /// Directory of the job relative to the results directory.
+    dir: String,
+    command: String,
+}
+
+/// One job of `JobResults::write`.
+pub struct JobResult<'a> {
+    pub sequence_number: usize,
+    pub input_line_number: &'a InputLineNumber,
+    pub input_values: &'a [OsString],
+    pub command_and_args: &'a OwnedCommandAndArgs,
+    pub command_result: &'a CommandResult,
+    /// None if the job produced no output, e.g. because it could not be spawned.
+    pub output: Option<&'a ChildProcessOutput>,
+}
+
+/// Writes the results of each job to its own directory under --results.
+pub struct JobResults {
+    dir: PathBuf,
+    layout: ResultsLayout,
+    index_format: ResultsIndexFormat,
+    index_writer: Mutex<BufWriter<File>>,
+}
+
+impl JobResults {
+    pub async fn new(command_line_args: &CommandLineArgs) -> anyhow::Result<Option<Self>> {
+        let Some(dir) = &command_line_args.results else {
+            return Ok(None);
+        };
+
+        tokio::fs::create_dir_all(dir)
+            .await
+            .with_context(|| format!("error creating results dir = '{}'", dir))?;
+
+        let dir = PathBuf::from(dir);
+
+        let index_format = command_line_args.results_index_format;
+
+        let index_file_name = dir.join(match index_format {
+            ResultsIndexFormat::Csv => "index.csv",
+            ResultsIndexFormat::Jsonl => "index.jsonl",
+        });
+
+        let index_file = File::create(&index_file_name).await.with_context(|| {
+            format!(
+                "error creating results index = '{}'",
+                index_file_name.display()
+            )
+        })?;
+
+        let mut index_writer = BufWriter::new(index_file);
+
+        if index_format == ResultsIndexFormat::Csv {
+            index_writer
+                .write_all(CSV_HEADER.as_bytes())
+                .await
+                .context("error writing results index header")?;
+        }
+
+        Ok(Some(Self {
+            dir,
+            layout: command_line_args.results_layout,
+            index_format,
+            index_writer: Mutex::new(index_writer),
+        }))
+    }
+
+    /// Directory of a job relative to the results directory.
+    fn job_dir(&self, sequence_number: usize, input_values: &[OsString]) -> PathBuf {
+        match self.layout {
+            ResultsLayout::Args if !input_values.is_empty() => input_values
+                .iter()
+                .enumerate()
+                .flat_map(|(i, value)| [(i + 1).to_string(), dir_name(&value.to_string_lossy())])
+                .collect(),
+            _ => PathBuf::from(sequence_number.to_string()),
+        }
+    }
+
+    fn format_index_entry(&self, job_result: &JobResult<'_>, job_dir: &Path) -> String {
+        let command_result = job_result.command_result;
+        let outcome = &command_result.outcome;
+
+        let entry = IndexEntry {
+            seq: job_result.sequence_number,
+            input: job_result.input_line_number.to_string(),
+            start_time: epoch_seconds(command_result.start_time),
+            runtime: command_result.runtime.as_secs_f64(),
+            status: outcome.status_name(),
+            exit_code: outcome.exit_code(),
+            signal: outcome.signal(),
+            stdout_bytes: command_result.stdout_bytes,
+            stderr_bytes: command_result.stderr_bytes,
+            dir: job_dir.to_string_lossy().into_owned(),
+            command: job_result.command_and_args.command_line(),
+        };
+
+        match self.index_format {
+            ResultsIndexFormat::Csv => {
+                let mut writer = csv::WriterBuilder::new()
+                    .has_headers(false)
+                    .from_writer(vec![]);
+
+                if let Err(e) = writer.serialize(&entry) {
+                    warn!("results index format error: {}", e);
+                }
+
+                writer
+                    .into_inner()
+                    .map(|bytes| String::from_utf8_lossy(&bytes).into_owned())
+                    .unwrap_or_default()
+            }
+            ResultsIndexFormat::Jsonl => {
+                let mut line = serde_json::to_string(&entry).unwrap_or_default();
+                line.push('\n');
+                line
+            }
+        }
+    }
+
+    async fn write_job_files(
+        &self,
+        job_result: &JobResult<'_>,
+        job_dir: &Path,
+    ) -> anyhow::Result<()> {
+        let command_result = job_result.command_result;
+
+        tokio::fs::create_dir_all(job_dir).await?;
+
+        let files = [
+            (
+                "seq",
+                format!("{}\n", job_result.sequence_number).into_bytes(),
+            ),
+            (
+                "cmd",
+                format!("{}\n", job_result.command_and_args.command_line()).into_bytes(),
+            ),
+            (
+                "exit_code",
+                format!("{}\n", command_result.outcome.exit_code().unwrap_or(-1)).into_bytes(),
+            ),
+            (
+                "runtime",
+                format!("{:.3}\n", command_result.runtime.as_secs_f64()).into_bytes(),
+            ),
+        ];
+
+        for (file_name, contents) in files {
+            tokio::fs::write(job_dir.join(file_name), contents).await?;
+        }
+
+        let empty = OutputBuffer::default();
+        let (stdout, stderr) = job_result
+            .output
+            .map_or((&empty, &empty), |output| (&output.stdout, &output.stderr));
+
+        for (file_name, buffer) in [("stdout", stdout), ("stderr", stderr)] {
+            let mut file = File::create(job_dir.join(file_name)).await?;
+            buffer.copy_to(&mut file).await?;
+            file.flush().await?;
+        }
+
+        Ok(())
+    }
+
+    pub async fn write(&self, job_result: JobResult<'_>) {
+        let job_dir = self.job_dir(job_result.sequence_number, job_result.input_values);
+        let job_path = self.dir.join(&job_dir);
+
+        if let Err(e) = self.write_job_files(&job_result, &job_path).await {
+            warn!(
+                "results write error dir = '{}': {:#}",
+                job_path.display(),
+                e
+            );
+        }
+
+        let entry = self.format_index_entry(&job_result, &job_dir);
+
+        let mut index_writer = self.index_writer.lock().await;
+
+        if let Err(e) = index_writer.write_all(entry.as_bytes()).await {
+            warn!("results index write error: {}", e);
+        }
+    }
+
+    pub async fn flush(&self) -> anyhow::Result<()> {
+        self.index_writer
+            .lock()
+            .await
+            .flush()
+            .await
+            .context("error flushing results index")
+    }
+}
+
+/// Directory name for an input value, which must not be empty, "." or ".." or contain "/".
+fn dir_name(value: &str) -> String {
+    let mut name = value.replace(['/', '\0'], "_");
+
+    if name.is_empty() || name == "." || name == ".." {
+        name = name.replace('.', "_");
+        name.push('_');
+    }
+
+    if name.len() > MAX_DIR_NAME_BYTES {
+        let mut end = MAX_DIR_NAME_BYTES;
+        while !name.is_char_boundary(end) {
+            end -= 1;
+        }
+        name.truncate(end);
+    }
+
+    name
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_dir_name() {
+        assert_eq!(dir_name("a.txt"), "a.txt");
+        assert_eq!(dir_name("dir/a b.txt"), "dir_a b.txt");
+        assert_eq!(dir_name(""), "_");
+        assert_eq!(dir_name("."), "__");
+        assert_eq!(dir_name(".."), "___");
+        assert_eq!(dir_name(&"é".repeat(150)), "é".repeat(100));
+    }
+}
@@ -102,12 +102,43 @@ fn timeout_sleep_commands_from_args() {
         .arg("0")
         .arg("5")
         .assert()
-        .success()
+        .failure()
+        .code(1)
         .stdout(
             (predicate::str::contains("\n").count(1))
-                .and(predicate::str::contains("timeout").count(1)),
+                .and(predicate::str::contains("timeout: deadline has elapsed").count(1)),
         )
-        .stderr(predicate::str::is_empty());
+        .stderr(predicate::str::contains("timeouts=1").count(1));
+}
+
+#[test]
+fn fails_exit_status_commands_from_args() {
+    rust_parallel()
+        .arg("false")
+        .arg(":::")
+        .arg("A")
+        .arg("B")
+        .arg("C")
+        .assert()
+        .failure()
+        .code(3)
+        .stderr(
+            predicate::str::contains("command failures:")
+                .and(predicate::str::contains("exit_status_errors=3").count(1)),
+        );
+}
+
+#[test]
+fn fails_spawn_error_commands_from_args() {
+    rust_parallel()
+        .arg("--disable-path-cache")
+        .arg("./does_not_exist")
+        .arg(":::")
+        .arg("A")
+        .assert()
+        .failure()
+        .code(1)
+        .stderr(predicate::str::contains("spawn_errors=1").count(1));
 }
 
 #[test]
@@ -375,3 +406,1422 @@ fn runs_regex_command_with_dollar_signs() {
         .stdout(predicate::eq(expected_stdout))
         .stderr(predicate::str::is_empty());
 }
+
+#[test]
+fn halt_soon_fail_stops_starting_commands() {
+    rust_parallel()
+        .arg("-j1")
+        .arg("--halt")
+        .arg("soon,fail=1")
+        .arg("-s")
+        .arg(":::")
+        .arg("echo A")
+        .arg("false")
+        .arg("echo B")
+        .arg("echo C")
+        .assert()
+        .failure()
+        .code(1)
+        .stdout(
+            predicate::str::starts_with("A\n")
+                .and(predicate::str::contains("halting due to halt policy").count(1))
+                .and(predicate::str::contains("B\n").not())
+                .and(predicate::str::contains("C\n").not()),
+        )
+        .stderr(predicate::str::contains("command failures:"));
+}
+
+#[test]
+fn halt_soon_fail_percent_of_total_commands() {
+    // Halts once 3 of the 6 commands have failed, not at the first failure.
+    rust_parallel()
+        .arg("-j1")
+        .arg("--halt")
+        .arg("soon,fail=50%")
+        .arg("-s")
+        .arg(":::")
+        .arg("false")
+        .arg("echo B")
+        .arg("false")
+        .arg("echo D")
+        .arg("false")
+        .arg("echo F")
+        .assert()
+        .failure()
+        .code(3)
+        .stdout(
+            predicate::str::contains("B\nD\n")
+                .and(predicate::str::contains("halting due to halt policy").count(1))
+                .and(predicate::str::contains("F\n").not()),
+        )
+        .stderr(predicate::str::contains("exit_status_errors=3"));
+}
+
+#[test]
+fn halt_now_fail_kills_running_commands() {
+    rust_parallel()
+        .arg("-j2")
+        .arg("--halt")
+        .arg("now,fail=1")
+        .arg("-s")
+        .arg(":::")
+        .arg("sleep 0.2; false")
+        .arg("sleep 5; echo slow")
+        .timeout(std::time::Duration::from_secs(3))
+        .assert()
+        .failure()
+        .code(1)
+        .stdout(
+            predicate::str::contains("killed command due to halt")
+                .count(1)
+                .and(predicate::str::contains("slow\n").not()),
+        )
+        .stderr(predicate::str::contains("command failures:"));
+}
+
+#[test]
+fn fails_invalid_halt_policy() {
+    rust_parallel()
+        .arg("--halt")
+        .arg("sometime,fail=1")
+        .assert()
+        .failure()
+        .stdout(predicate::str::is_empty())
+        .stderr(predicate::str::contains(
+            "invalid value 'sometime,fail=1' for '--halt <HALT>'",
+        ));
+}
+
+#[test]
+fn runs_keep_order_commands_from_args() {
+    rust_parallel()
+        .arg("-j4")
+        .arg("-k")
+        .arg("-s")
+        .arg(":::")
+        .arg("sleep 0.6; echo A")
+        .arg("sleep 0.4; echo B")
+        .arg("sleep 0.2; echo C")
+        .arg("echo D")
+        .assert()
+        .success()
+        .stdout(predicate::eq("A\nB\nC\nD\n"))
+        .stderr(predicate::str::is_empty());
+}
+
+#[test]
+fn runs_keep_order_stdin_small_buffer() {
+    let stdin = r#"
+        sleep 0.3; echo A
+        sleep 0.2; echo B
+        true
+        sleep 0.1; echo C
+        echo D
+    "#;
+
+    rust_parallel()
+        .write_stdin(stdin)
+        .arg("-j4")
+        .arg("--keep-order")
+        .arg("--keep-order-buffer=2")
+        .arg("-s")
+        .assert()
+        .success()
+        .stdout(predicate::eq("A\nB\nC\nD\n"))
+        .stderr(predicate::str::is_empty());
+}
+
+#[test]
+fn runs_line_buffer_commands_from_args() {
+    rust_parallel()
+        .arg("-j2")
+        .arg("--line-buffer")
+        .arg("-s")
+        .arg(":::")
+        .arg("echo A1; sleep 0.6; echo A2")
+        .arg("sleep 0.3; echo B1")
+        .assert()
+        .success()
+        .stdout(predicate::eq("A1\nB1\nA2\n"))
+        .stderr(predicate::str::is_empty());
+}
+
+#[test]
+fn fails_line_buffer_with_keep_order() {
+    rust_parallel()
+        .arg("--line-buffer")
+        .arg("--keep-order")
+        .assert()
+        .failure()
+        .stdout(predicate::str::is_empty())
+        .stderr(predicate::str::contains("cannot be used with"));
+}
+
+#[test]
+fn runs_tag_commands_from_args_j1() {
+    rust_parallel()
+        .arg("-j1")
+        .arg("--tag")
+        .arg("-s")
+        .arg(":::")
+        .arg("echo A; echo A2")
+        .arg("echo B")
+        .assert()
+        .success()
+        .stdout(predicate::eq(
+            "echo A; echo A2\tA\necho A; echo A2\tA2\necho B\tB\n",
+        ))
+        .stderr(predicate::str::is_empty());
+}
+
+#[test]
+fn runs_tagstring_from_file_j1() {
+    rust_parallel()
+        .arg("-j1")
+        .arg("-i")
+        .arg("file.txt")
+        .arg("--tagstring")
+        .arg("{#} {line} {}:")
+        .arg("echo")
+        .assert()
+        .success()
+        .stdout(predicate::eq(
+            "1 file.txt:1 hello:\thello\n2 file.txt:2 from:\tfrom\n3 file.txt:3 input:\tinput\n4 file.txt:4 file:\tfile\n",
+        ))
+        .stderr(predicate::str::is_empty());
+}
+
+#[test]
+fn runs_tagstring_replacement_strings_j1() {
+    rust_parallel()
+        .arg("-j1")
+        .arg("--tagstring")
+        .arg("{1/.}:{2}:{#}:{%}")
+        .arg("echo")
+        .arg(":::")
+        .arg("dir/a.txt")
+        .arg("dir/b.txt")
+        .arg(":::")
+        .arg("x")
+        .assert()
+        .success()
+        .stdout(predicate::eq(
+            "a:x:1:1\tdir/a.txt x\nb:x:2:1\tdir/b.txt x\n",
+        ))
+        .stderr(predicate::str::is_empty());
+}
+
+#[test]
+fn runs_timestamp_commands_from_args() {
+    rust_parallel()
+        .arg("--timestamp")
+        .arg("echo")
+        .arg(":::")
+        .arg("A")
+        .assert()
+        .success()
+        .stdout(
+            predicate::str::is_match(r"^\d{4}-\d{2}-\d{2}T\d{2}:\d{2}:\d{2}\.\d{3}Z\tA\n$")
+                .unwrap(),
+        )
+        .stderr(predicate::str::is_empty());
+}
+
+fn temp_file_path(name: &str) -> std::path::PathBuf {
+    std::env::temp_dir().join(format!("rust-parallel-{}-{}", std::process::id(), name))
+}
+
+#[test]
+fn writes_joblog_tsv() {
+    let joblog_path = temp_file_path("joblog.tsv");
+
+    rust_parallel()
+        .arg("-j1")
+        .arg("--joblog")
+        .arg(&joblog_path)
+        .arg("-s")
+        .arg(":::")
+        .arg("echo hi")
+        .arg("exit 3")
+        .assert()
+        .failure()
+        .code(1);
+
+    let joblog = std::fs::read_to_string(&joblog_path).unwrap();
+    std::fs::remove_file(&joblog_path).unwrap();
+
+    let rows: Vec<Vec<&str>> = joblog
+        .lines()
+        .map(|line| line.split('\t').collect())
+        .collect();
+
+    assert_eq!(rows.len(), 3);
+    assert_eq!(
+        rows[0],
+        vec![
+            "Seq",
+            "Input",
+            "Starttime",
+            "JobRuntime",
+            "Exitval",
+            "Signal",
+            "StdoutBytes",
+            "StderrBytes",
+            "Command"
+        ]
+    );
+    assert_eq!(rows[1][0..2], ["1", "command_line_args:1"]);
+    assert_eq!(rows[1][4..], ["0", "0", "3", "0", "/bin/bash -c echo hi"]);
+    assert_eq!(rows[2][0..2], ["2", "command_line_args:2"]);
+    assert_eq!(rows[2][4..], ["3", "0", "0", "0", "/bin/bash -c exit 3"]);
+}
+
+#[test]
+fn writes_joblog_jsonl() {
+    let joblog_path = temp_file_path("joblog.jsonl");
+
+    rust_parallel()
+        .arg("-j1")
+        .arg("--joblog")
+        .arg(&joblog_path)
+        .arg("--joblog-format=jsonl")
+        .arg("-t1")
+        .arg("-s")
+        .arg(":::")
+        .arg("echo hi")
+        .arg("sleep 5")
+        .assert()
+        .failure()
+        .code(1);
+
+    let joblog = std::fs::read_to_string(&joblog_path).unwrap();
+    std::fs::remove_file(&joblog_path).unwrap();
+
+    let lines: Vec<&str> = joblog.lines().collect();
+
+    assert_eq!(lines.len(), 2);
+    assert!(lines[0].starts_with(r#"{"seq":1,"input":"command_line_args:1","#));
+    assert!(lines[0].contains(r#""status":"success","exit_code":0,"signal":null,"stdout_bytes":3,"stderr_bytes":0,"command":"/bin/bash -c echo hi"}"#));
+    assert!(lines[1].starts_with(r#"{"seq":2,"input":"command_line_args:2","#));
+    assert!(lines[1].contains(r#""status":"timeout","exit_code":null"#));
+}
+
+#[test]
+fn resumes_from_joblog() {
+    let joblog_path = temp_file_path("resume-joblog.tsv");
+
+    rust_parallel()
+        .arg("-j1")
+        .arg("--joblog")
+        .arg(&joblog_path)
+        .arg("-s")
+        .arg(":::")
+        .arg("echo A")
+        .arg("exit 1")
+        .assert()
+        .failure()
+        .stdout(predicate::str::starts_with("A\n"));
+
+    rust_parallel()
+        .arg("-j1")
+        .arg("--joblog")
+        .arg(&joblog_path)
+        .arg("--resume")
+        .arg("-s")
+        .arg(":::")
+        .arg("echo A")
+        .arg("echo B")
+        .arg("echo C")
+        .assert()
+        .success()
+        .stdout(predicate::eq("C\n"))
+        .stderr(predicate::str::is_empty());
+
+    rust_parallel()
+        .arg("-j1")
+        .arg("--joblog")
+        .arg(&joblog_path)
+        .arg("--resume-failed")
+        .arg("-s")
+        .arg(":::")
+        .arg("echo A")
+        .arg("echo B")
+        .arg("echo C")
+        .arg("echo D")
+        .assert()
+        .success()
+        .stdout(predicate::eq("B\nD\n"))
+        .stderr(predicate::str::is_empty());
+
+    let joblog = std::fs::read_to_string(&joblog_path).unwrap();
+    std::fs::remove_file(&joblog_path).unwrap();
+
+    let sequence_numbers: Vec<&str> = joblog
+        .lines()
+        .skip(1)
+        .map(|line| line.split('\t').next().unwrap())
+        .collect();
+
+    assert_eq!(sequence_numbers, vec!["1", "2", "3", "2", "4"]);
+}
+
+#[test]
+fn resumes_from_empty_joblog() {
+    let joblog_path = temp_file_path("empty-joblog.tsv");
+    std::fs::write(&joblog_path, "").unwrap();
+
+    rust_parallel()
+        .arg("--joblog")
+        .arg(&joblog_path)
+        .arg("--resume")
+        .arg("-s")
+        .arg(":::")
+        .arg("echo A")
+        .assert()
+        .success()
+        .stdout(predicate::eq("A\n"))
+        .stderr(predicate::str::is_empty());
+
+    rust_parallel()
+        .arg("-j1")
+        .arg("--joblog")
+        .arg(&joblog_path)
+        .arg("--resume")
+        .arg("-s")
+        .arg(":::")
+        .arg("echo A")
+        .arg("echo B")
+        .assert()
+        .success()
+        .stdout(predicate::eq("B\n"))
+        .stderr(predicate::str::is_empty());
+
+    let joblog = std::fs::read_to_string(&joblog_path).unwrap();
+    std::fs::remove_file(&joblog_path).unwrap();
+
+    assert!(joblog.starts_with("Seq\tInput\t"));
+    assert_eq!(joblog.lines().count(), 3);
+}
+
+#[cfg(unix)]
+#[test]
+fn writes_joblog_entries_before_being_killed() {
+    let joblog_path = temp_file_path("killed-joblog.tsv");
+
+    let mut child = rust_parallel_raw_command()
+        .arg("-j1")
+        .arg("--joblog")
+        .arg(&joblog_path)
+        .arg("-s")
+        .arg(":::")
+        .arg("echo A")
+        .arg("sleep 3")
+        .stdout(std::process::Stdio::null())
+        .spawn()
+        .unwrap();
+
+    std::thread::sleep(std::time::Duration::from_millis(1000));
+
+    child.kill().unwrap();
+    child.wait().unwrap();
+
+    let joblog = std::fs::read_to_string(&joblog_path).unwrap();
+    std::fs::remove_file(&joblog_path).unwrap();
+
+    let lines: Vec<&str> = joblog.lines().collect();
+
+    assert_eq!(lines.len(), 2);
+    assert!(lines[1].starts_with("1\tcommand_line_args:1\t"));
+}
+
+#[test]
+fn resumes_failed_from_joblog_jsonl() {
+    let joblog_path = temp_file_path("resume-joblog.jsonl");
+
+    rust_parallel()
+        .arg("-j1")
+        .arg("--joblog")
+        .arg(&joblog_path)
+        .arg("--joblog-format=jsonl")
+        .arg("-i")
+        .arg("file.txt")
+        .arg("-s")
+        .arg("test")
+        .arg("hello")
+        .arg("=")
+        .assert()
+        .failure()
+        .code(3);
+
+    rust_parallel()
+        .arg("-j1")
+        .arg("--joblog")
+        .arg(&joblog_path)
+        .arg("--joblog-format=jsonl")
+        .arg("--resume-failed")
+        .arg("-i")
+        .arg("file.txt")
+        .arg("echo")
+        .assert()
+        .success()
+        .stdout(predicate::eq("from\ninput\nfile\n"))
+        .stderr(predicate::str::is_empty());
+
+    std::fs::remove_file(&joblog_path).unwrap();
+}
+
+#[test]
+fn retries_failed_commands() {
+    let marker_path = temp_file_path("retry-marker");
+    let _ = std::fs::remove_file(&marker_path);
+
+    // Fails on the first attempt and succeeds on the retry.
+    let command = format!(
+        "if [ -e {0} ]; then echo retried; else echo first; touch {0}; exit 1; fi",
+        marker_path.display()
+    );
+
+    rust_parallel()
+        .arg("--retries=2")
+        .arg("--retry-delay=0.01")
+        .arg("-s")
+        .arg(":::")
+        .arg(&command)
+        .assert()
+        .success()
+        .stdout(
+            predicate::str::contains("retrying command")
+                .and(predicate::str::contains("retried\n"))
+                .and(predicate::str::contains("first\n").not()),
+        )
+        .stderr(predicate::str::is_empty());
+
+    std::fs::remove_file(&marker_path).unwrap();
+}
+
+#[test]
+fn fails_retries_with_line_buffer() {
+    rust_parallel()
+        .arg("--retries=1")
+        .arg("--line-buffer")
+        .assert()
+        .failure()
+        .stdout(predicate::str::is_empty())
+        .stderr(predicate::str::contains("cannot be used with"));
+}
+
+#[test]
+fn fails_after_retries_exhausted() {
+    rust_parallel()
+        .arg("--retries=2")
+        .arg("-s")
+        .arg(":::")
+        .arg("echo attempt; exit 1")
+        .assert()
+        .failure()
+        .code(1)
+        .stderr(
+            predicate::str::contains("commands_run=1,retries=2,")
+                .and(predicate::str::contains("exit_status_errors=1")),
+        );
+}
+
+#[test]
+fn timeout_escalates_signals_to_process_group() {
+    let joblog_path = temp_file_path("timeout-joblog.tsv");
+
+    let start = std::time::Instant::now();
+
+    // The shell and its sleep child both ignore TERM, so only KILL ends them.
+    rust_parallel()
+        .arg("-t")
+        .arg("0.5")
+        .arg("--timeout-signals=TERM,200ms,KILL")
+        .arg("--joblog")
+        .arg(&joblog_path)
+        .arg("-s")
+        .arg(":::")
+        .arg("trap '' TERM; sleep 10; echo done")
+        .assert()
+        .failure()
+        .code(1)
+        .stdout(
+            predicate::str::contains("timeout: deadline has elapsed")
+                .and(predicate::str::contains("done\n").not()),
+        )
+        .stderr(predicate::str::contains("timeouts=1"));
+
+    assert!(start.elapsed() < std::time::Duration::from_secs(5));
+
+    let joblog = std::fs::read_to_string(&joblog_path).unwrap();
+    std::fs::remove_file(&joblog_path).unwrap();
+
+    let row: Vec<&str> = joblog.lines().nth(1).unwrap().split('\t').collect();
+
+    // Exitval and Signal columns.
+    assert_eq!(row[4..6], ["-1", "9"]);
+}
+
+#[cfg(unix)]
+#[test]
+fn timeout_kills_process_group_after_child_exits() {
+    let leak_path = temp_file_path("timeout-leak");
+
+    // The shell exits on TERM but its background subshell ignores TERM, so it must be
+    // killed by the final KILL to the process group.
+    rust_parallel()
+        .arg("-t")
+        .arg("0.3")
+        .arg("-s")
+        .arg(":::")
+        .arg(format!(
+            "(trap '' TERM; sleep 1.5; echo leaked > {}) & wait",
+            leak_path.display()
+        ))
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("timeouts=1"));
+
+    std::thread::sleep(std::time::Duration::from_secs(2));
+
+    let leaked = leak_path.exists();
+    let _ = std::fs::remove_file(&leak_path);
+
+    assert!(!leaked);
+}
+
+#[test]
+fn fails_invalid_timeout_signals() {
+    rust_parallel()
+        .arg("--timeout-signals=TERM,STOP")
+        .arg(":::")
+        .arg("true")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("`STOP` isn't a supported signal"));
+}
+
+#[cfg(unix)]
+fn spawn_and_interrupt(args: &[&str], signal_count: usize) -> std::process::Output {
+    let child = rust_parallel_raw_command()
+        .args(args)
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    for _ in 0..signal_count {
+        std::thread::sleep(std::time::Duration::from_millis(500));
+
+        let status = Command::new("kill")
+            .arg("-INT")
+            .arg(child.id().to_string())
+            .status()
+            .unwrap();
+        assert!(status.success());
+    }
+
+    child.wait_with_output().unwrap()
+}
+
+#[cfg(unix)]
+#[test]
+fn interrupt_forwards_signal_and_flushes_output() {
+    let start = std::time::Instant::now();
+
+    let output = spawn_and_interrupt(
+        &["-s", ":::", "trap 'echo cleaned up; exit 1' INT; sleep 10"],
+        1,
+    );
+
+    assert!(start.elapsed() < std::time::Duration::from_secs(5));
+    assert_eq!(output.status.code(), Some(130));
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("cleaned up\n"));
+    assert!(stdout.contains("interrupted by Int signal"));
+}
+
+#[cfg(unix)]
+#[test]
+fn second_interrupt_kills_commands() {
+    let start = std::time::Instant::now();
+
+    let output = spawn_and_interrupt(&["-s", ":::", "trap '' INT; sleep 10"], 2);
+
+    assert!(start.elapsed() < std::time::Duration::from_secs(5));
+    assert_eq!(output.status.code(), Some(130));
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("killing running commands"));
+}
+
+#[test]
+fn runs_replacement_strings_commands_from_args() {
+    rust_parallel()
+        .arg("-j1")
+        .arg("echo")
+        .arg("{}")
+        .arg("{.}")
+        .arg("{/}")
+        .arg("{//}")
+        .arg("{/.}")
+        .arg("{#}")
+        .arg("slot={%}")
+        .arg(":::")
+        .arg("dir/a.txt")
+        .arg("b.tar.gz")
+        .assert()
+        .success()
+        .stdout("dir/a.txt dir/a a.txt dir a 1 slot=1\nb.tar.gz b.tar b.tar.gz . b.tar 2 slot=1\n")
+        .stderr(predicate::str::is_empty());
+}
+
+#[test]
+fn job_slot_leaves_input_values_alone() {
+    rust_parallel()
+        .arg("-j1")
+        .arg("echo")
+        .arg("{%}")
+        .arg("{}")
+        .arg(":::")
+        .arg("a{%}")
+        .assert()
+        .success()
+        .stdout("1 a{%}\n")
+        .stderr(predicate::str::is_empty());
+
+    rust_parallel()
+        .arg("-j1")
+        .arg("-s")
+        .arg("echo '{}' {%}-{%}")
+        .arg(":::")
+        .arg("b{%}")
+        .assert()
+        .success()
+        .stdout("b{%} 1-1\n")
+        .stderr(predicate::str::is_empty());
+}
+
+#[test]
+fn runs_replacement_strings_from_file() {
+    rust_parallel()
+        .arg("-j1")
+        .arg("-i")
+        .arg("file.txt")
+        .arg("-s")
+        .arg("echo {#}-{}")
+        .assert()
+        .success()
+        .stdout("1-hello\n2-from\n3-input\n4-file\n")
+        .stderr(predicate::str::is_empty());
+}
+
+#[test]
+fn runs_positional_replacement_strings_commands_from_args() {
+    rust_parallel()
+        .arg("-j1")
+        .arg("echo")
+        .arg("--in")
+        .arg("{1/}")
+        .arg("--mode")
+        .arg("{2}")
+        .arg("--out")
+        .arg("{1.}.out")
+        .arg(":::")
+        .arg("dir/a.txt")
+        .arg("b.txt")
+        .arg(":::")
+        .arg("fast")
+        .arg("slow")
+        .assert()
+        .success()
+        .stdout(
+            "--in a.txt --mode fast --out dir/a.out\n--in a.txt --mode slow --out dir/a.out\n--in b.txt --mode fast --out b.out\n--in b.txt --mode slow --out b.out\n",
+        )
+        .stderr(predicate::str::is_empty());
+}
+
+#[test]
+fn runs_positional_replacement_strings_input_files_side_by_side() {
+    rust_parallel()
+        .arg("-j1")
+        .arg("-i")
+        .arg("file.txt")
+        .arg("-i")
+        .arg("csv_file.txt")
+        .arg("echo")
+        .arg("{2}={1}")
+        .assert()
+        .success()
+        .stdout("1,2,3=hello\nfoo,bar,baz=from\n=input\n=file\n")
+        .stderr(predicate::str::is_empty());
+}
+
+#[test]
+fn runs_linked_argument_groups() {
+    rust_parallel()
+        .arg("-k")
+        .arg("echo")
+        .arg("{1}-{2}")
+        .arg(":::")
+        .arg("a")
+        .arg("b")
+        .arg("c")
+        .arg(":::+")
+        .arg("1")
+        .arg("2")
+        .arg("3")
+        .assert()
+        .success()
+        .stdout("a-1\nb-2\nc-3\n")
+        .stderr(predicate::str::is_empty());
+}
+
+#[test]
+fn fails_linked_argument_groups_length_mismatch() {
+    rust_parallel()
+        .arg("echo")
+        .arg(":::")
+        .arg("a")
+        .arg("b")
+        .arg(":::+")
+        .arg("1")
+        .assert()
+        .failure()
+        .code(1)
+        .stdout(predicate::str::contains(
+            "linked argument groups have different lengths [2, 1]",
+        ));
+}
+
+#[test]
+fn runs_argument_groups_from_files() {
+    rust_parallel()
+        .arg("-k")
+        .arg("echo")
+        .arg(":::")
+        .arg("A")
+        .arg("B")
+        .arg("::::")
+        .arg("argument_file.txt")
+        .assert()
+        .success()
+        .stdout("A x\nA y\nB x\nB y\n")
+        .stderr(predicate::str::is_empty());
+}
+
+#[test]
+fn runs_linked_argument_groups_from_files_and_stdin() {
+    rust_parallel()
+        .arg("-k")
+        .arg("echo")
+        .arg("{1}={2}")
+        .arg("::::")
+        .arg("argument_file.txt")
+        .arg("::::+")
+        .arg("-")
+        .write_stdin("1\n2\n")
+        .assert()
+        .success()
+        .stdout("x=1\ny=2\n")
+        .stderr(predicate::str::is_empty());
+}
+
+#[cfg(unix)]
+#[test]
+fn runs_non_utf8_argument_files() {
+    rust_parallel()
+        .arg("-k")
+        .arg("echo")
+        .arg("::::")
+        .arg("-")
+        .write_stdin(&b"caf\xe9\nx\n"[..])
+        .assert()
+        .success()
+        .stdout(&b"caf\xe9\nx\n"[..])
+        .stderr(predicate::str::is_empty());
+}
+
+#[test]
+fn fails_argument_file_not_found() {
+    rust_parallel()
+        .arg("echo")
+        .arg("::::")
+        .arg("missing_argument_file.txt")
+        .assert()
+        .failure()
+        .code(1)
+        .stdout(predicate::str::contains(
+            "error opening input file file_name = 'missing_argument_file.txt'",
+        ));
+}
+
+#[test]
+fn runs_colsep_columns_from_file() {
+    rust_parallel()
+        .arg("-k")
+        .arg("-i")
+        .arg("csv_file.txt")
+        .arg("--colsep")
+        .arg(",")
+        .arg("echo")
+        .arg("{3}-{1}")
+        .assert()
+        .success()
+        .stdout("3-1\nbaz-foo\n")
+        .stderr(predicate::str::is_empty());
+}
+
+#[test]
+fn runs_colsep_skips_lines_with_too_few_columns() {
+    rust_parallel()
+        .arg("-k")
+        .arg("--colsep")
+        .arg(r"\s+")
+        .arg("echo")
+        .arg("{2}")
+        .write_stdin("a b\nc\nd e\n")
+        .assert()
+        .failure()
+        .code(1)
+        .stdout(
+            predicate::str::contains("b\ne\n").and(predicate::str::contains(
+                r#"input line has 1 columns but the command uses {2}: "c""#,
+            )),
+        )
+        .stderr(predicate::str::contains("invalid_inputs=1"));
+}
+
+#[test]
+fn runs_csv_records_with_stray_quotes() {
+    let stdin = "size,name\n5\" screen,a\n7\" screen,b\n";
+
+    rust_parallel()
+        .arg("-k")
+        .arg("--csv")
+        .arg("echo")
+        .arg("{name}={size}")
+        .write_stdin(stdin)
+        .assert()
+        .success()
+        .stdout("a=5\" screen\nb=7\" screen\n")
+        .stderr(predicate::str::is_empty());
+}
+
+#[test]
+fn runs_csv_header_named_columns() {
+    rust_parallel()
+        .arg("-k")
+        .arg("-i")
+        .arg("csv_header_file.csv")
+        .arg("--csv")
+        .arg("echo")
+        .arg("{file}={col:notes}")
+        .assert()
+        .failure()
+        .code(1)
+        .stdout(
+            predicate::str::contains("a.txt=one, two\nb.txt=multi\nline\nd.txt=plain\n").and(
+                predicate::str::contains(
+                    "skipping input csv_header_file.csv:5 that can't be parsed: CSV record has 1 fields but the header has 2",
+                ),
+            ),
+        )
+        .stderr(predicate::str::contains("invalid_inputs=1"));
+}
+
+#[test]
+fn fails_csv_unknown_column() {
+    rust_parallel()
+        .arg("-i")
+        .arg("csv_header_file.csv")
+        .arg("--csv")
+        .arg("echo")
+        .arg("{col:size}")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            r#"unknown CSV column {col:size}, header columns are ["file", "notes"]"#,
+        ))
+        .stderr(predicate::str::is_empty());
+}
+
+#[test]
+fn runs_jsonl_field_paths_and_job_env() {
+    rust_parallel()
+        .arg("-k")
+        .arg("-i")
+        .arg("jsonl_file.jsonl")
+        .arg("--jsonl")
+        .arg("-s")
+        .arg("echo ${GREETING:-none} {.user.id} {.files[0]}")
+        .assert()
+        .failure()
+        .code(1)
+        .stdout(predicate::str::contains("none 1 a.txt\nhi 2 b.txt\n").and(
+            predicate::str::contains(
+                "skipping input jsonl_file.jsonl:2 that can't be parsed: invalid JSON",
+            ),
+        ))
+        .stderr(predicate::str::contains("invalid_inputs=1"));
+}
+
+#[test]
+fn fails_jsonl_invalid_json_lines() {
+    rust_parallel()
+        .arg("-i")
+        .arg("jsonl_file.jsonl")
+        .arg("--jsonl")
+        .arg("--invalid-json-lines=fail")
+        .arg("echo")
+        .arg("{.user.id}")
+        .assert()
+        .failure()
+        .code(1)
+        .stdout(predicate::str::contains(
+            "invalid JSON line jsonl_file.jsonl:2: invalid JSON",
+        ))
+        .stderr(predicate::str::is_empty());
+}
+
+#[test]
+fn fails_jsonl_invalid_json_lines_after_running_commands_finish() {
+    let marker_path = temp_file_path("jsonl-marker");
+    let _ = std::fs::remove_file(&marker_path);
+
+    let stdin = format!("{{\"marker\": \"{}\"}}\nnot json\n", marker_path.display());
+
+    rust_parallel()
+        .arg("--jsonl")
+        .arg("--invalid-json-lines=fail")
+        .arg("-s")
+        .arg("sleep 1; touch {.marker}; echo done")
+        .write_stdin(stdin)
+        .assert()
+        .failure()
+        .code(1)
+        .stdout(
+            predicate::str::contains("done\n")
+                .and(predicate::str::contains("invalid JSON line stdin:2")),
+        )
+        .stderr(predicate::str::is_empty());
+
+    assert!(marker_path.exists());
+
+    let _ = std::fs::remove_file(&marker_path);
+}
+
+#[test]
+fn timeout_jsonl_job_options() {
+    rust_parallel()
+        .arg("--jsonl")
+        .arg("sleep")
+        .arg("{.seconds}")
+        .write_stdin("{\"seconds\": 5, \"_job\": {\"timeout\": 0.5}}\n{\"seconds\": 0}\n")
+        .assert()
+        .failure()
+        .code(1)
+        .stdout(predicate::str::contains("timeout: deadline has elapsed").count(1))
+        .stderr(predicate::str::contains("timeouts=1").count(1));
+}
+
+#[test]
+fn runs_batches_of_max_args() {
+    rust_parallel()
+        .arg("-k")
+        .arg("-n")
+        .arg("2")
+        .arg("echo")
+        .arg("{#}:")
+        .arg("in/{}")
+        .write_stdin("a\nb\nc\nd\ne\n")
+        .assert()
+        .success()
+        .stdout("1: in/a in/b\n2: in/c in/d\n3: in/e\n")
+        .stderr(predicate::str::is_empty());
+}
+
+#[test]
+fn runs_batches_of_max_lines() {
+    rust_parallel()
+        .arg("-k")
+        .arg("-L")
+        .arg("2")
+        .arg("echo")
+        .write_stdin("a b\nc\nd\n")
+        .assert()
+        .success()
+        .stdout("a b c\nd\n")
+        .stderr(predicate::str::is_empty());
+}
+
+#[test]
+fn runs_xargs_batches_commands_from_args() {
+    rust_parallel()
+        .arg("-X")
+        .arg("echo")
+        .arg(":::")
+        .arg("A")
+        .arg("B")
+        .arg("C")
+        .assert()
+        .success()
+        .stdout("A B C\n")
+        .stderr(predicate::str::is_empty());
+}
+
+#[test]
+fn runs_pipe_blocks_in_input_order() {
+    let stdin = (1..=1000)
+        .map(|i| format!("line {i}\n"))
+        .collect::<String>();
+
+    rust_parallel()
+        .arg("--pipe")
+        .arg("--block-size")
+        .arg("1K")
+        .arg("cat")
+        .write_stdin(stdin.clone())
+        .assert()
+        .success()
+        .stdout(stdin)
+        .stderr(predicate::str::is_empty());
+}
+
+#[test]
+fn runs_pipe_max_records() {
+    rust_parallel()
+        .arg("--pipe")
+        .arg("-N")
+        .arg("2")
+        .arg("-s")
+        .arg("echo {#}: $(cat)")
+        .write_stdin("a\nb\nc\nd\ne\n")
+        .assert()
+        .success()
+        .stdout("1: a b\n2: c d\n3: e\n")
+        .stderr(predicate::str::is_empty());
+}
+
+#[test]
+fn runs_pipe_recstart() {
+    rust_parallel()
+        .arg("--pipe")
+        .arg("--block-size")
+        .arg("1")
+        .arg("--recstart")
+        .arg(">")
+        .arg("--recend")
+        .arg("")
+        .arg("wc")
+        .arg("-l")
+        .write_stdin(">a\nx\ny\n>b\nz\n")
+        .assert()
+        .success()
+        .stdout(predicate::str::is_match(r"^\s*3\n\s*2\n$").unwrap())
+        .stderr(predicate::str::is_empty());
+}
+
+#[test]
+fn runs_stdin_input() {
+    rust_parallel()
+        .arg("-k")
+        .arg("--stdin-input")
+        .arg("-s")
+        .arg("echo {#}: $(cat)")
+        .write_stdin("a b\nc\n")
+        .assert()
+        .success()
+        .stdout("1: a b\n2: c\n")
+        .stderr(predicate::str::is_empty());
+}
+
+#[test]
+fn runs_stdin_input_larger_than_pipe_buffer() {
+    // cat writes its output back while stdin is still being written.
+    let line = "x".repeat(4 * 1024 * 1024);
+
+    rust_parallel()
+        .arg("--stdin-input")
+        .arg("cat")
+        .write_stdin(format!("{line}\n"))
+        .assert()
+        .success()
+        .stdout(format!("{line}\n"))
+        .stderr(predicate::str::is_empty());
+}
+
+#[cfg(unix)]
+#[test]
+fn runs_non_utf8_stdin_input() {
+    rust_parallel()
+        .arg("--tag")
+        .arg("--stdin-input")
+        .arg("cat")
+        .write_stdin(&b"caf\xe9\n"[..])
+        .assert()
+        .success()
+        .stdout(&b"caf\xef\xbf\xbd\tcaf\xe9\n"[..])
+        .stderr(predicate::str::is_empty());
+}
+
+#[test]
+fn writes_results_dir() {
+    let results_path = temp_file_path("results");
+
+    rust_parallel()
+        .arg("--results")
+        .arg(&results_path)
+        .arg("-s")
+        .arg("echo out {}; echo err {} >&2; exit {}")
+        .arg(":::")
+        .arg("0")
+        .arg("3")
+        .assert()
+        .failure()
+        .code(1);
+
+    let read = |name: &str| std::fs::read_to_string(results_path.join(name)).unwrap();
+
+    assert_eq!(read("1/stdout"), "out 0\n");
+    assert_eq!(read("1/exit_code"), "0\n");
+    assert_eq!(read("2/stderr"), "err 3\n");
+    assert_eq!(read("2/exit_code"), "3\n");
+    assert!(read("2/cmd").ends_with("echo out 3; echo err 3 >&2; exit 3\n"));
+
+    let index = read("index.csv");
+    let mut rows: Vec<Vec<&str>> = index
+        .lines()
+        .skip(1)
+        .map(|line| line.split(',').collect())
+        .collect();
+    rows.sort();
+
+    std::fs::remove_dir_all(&results_path).unwrap();
+
+    assert_eq!(rows.len(), 2);
+    assert_eq!(rows[0][0..2], ["1", "command_line_args:1"]);
+    assert_eq!(rows[0][4..6], ["success", "0"]);
+    assert_eq!(rows[1][4..6], ["exit_status_error", "3"]);
+    assert_eq!(rows[1][9], "2");
+}
+
+#[test]
+fn writes_results_args_layout() {
+    let results_path = temp_file_path("results-args");
+
+    rust_parallel()
+        .arg("--results")
+        .arg(&results_path)
+        .arg("--results-layout")
+        .arg("args")
+        .arg("--results-index-format")
+        .arg("jsonl")
+        .arg("echo")
+        .arg(":::")
+        .arg("a/b")
+        .arg(":::")
+        .arg("c")
+        .assert()
+        .success();
+
+    let stdout = std::fs::read_to_string(results_path.join("1/a_b/2/c/stdout")).unwrap();
+    let index = std::fs::read_to_string(results_path.join("index.jsonl")).unwrap();
+
+    std::fs::remove_dir_all(&results_path).unwrap();
+
+    assert_eq!(stdout, "a/b c\n");
+    assert!(index.contains(r#""dir":"1/a_b/2/c""#));
+}
+
+#[test]
+fn spills_output_over_memory_limit_in_order() {
+    let expected = (1..=2000)
+        .chain(1..=3)
+        .map(|i| format!("{i}\n"))
+        .collect::<String>();
+
+    rust_parallel()
+        .arg("-k")
+        .arg("--output-memory-limit")
+        .arg("1K")
+        .arg("seq")
+        .arg(":::")
+        .arg("2000")
+        .arg("3")
+        .assert()
+        .success()
+        .stdout(expected)
+        .stderr(predicate::str::is_empty());
+}
+
+#[test]
+fn spills_tagged_stderr_over_memory_limit() {
+    let expected = (1..=500).map(|i| format!("A\t{i}\n")).collect::<String>();
+
+    rust_parallel()
+        .arg("--tag")
+        .arg("--output-memory-limit")
+        .arg("100")
+        .arg("-s")
+        .arg("seq 500 >&2; true")
+        .arg(":::")
+        .arg("A")
+        .assert()
+        .success()
+        .stdout(predicate::str::is_empty())
+        .stderr(expected);
+}
+
+/// Parses every line of `stdout` as a JSON object.
+fn json_lines(stdout: &[u8]) -> Vec<serde_json::Value> {
+    std::str::from_utf8(stdout)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect()
+}
+
+#[test]
+fn writes_json_output_format() {
+    let assert = rust_parallel()
+        .arg("-k")
+        .arg("--output-format")
+        .arg("json")
+        .arg("-s")
+        .arg(":::")
+        .arg("echo hi")
+        .arg(r#"printf "\377" >&2; exit 3"#)
+        .assert()
+        .failure()
+        .code(1);
+
+    let jobs = json_lines(&assert.get_output().stdout);
+
+    assert_eq!(jobs.len(), 2);
+
+    assert_eq!(jobs[0]["seq"], 1);
+    assert_eq!(jobs[0]["input"], "command_line_args:1");
+    assert_eq!(jobs[0]["args"][1], "echo hi");
+    assert_eq!(jobs[0]["status"], "success");
+    assert_eq!(jobs[0]["exit_code"], 0);
+    assert_eq!(jobs[0]["stdout"], "hi\n");
+    assert_eq!(jobs[0]["stdout_encoding"], "utf8");
+
+    assert_eq!(jobs[1]["status"], "exit_status_error");
+    assert_eq!(jobs[1]["exit_code"], 3);
+    assert_eq!(jobs[1]["stderr"], "/w==");
+    assert_eq!(jobs[1]["stderr_encoding"], "base64");
+}
+
+#[test]
+fn writes_json_output_format_for_timeouts() {
+    let assert = rust_parallel()
+        .arg("-t")
+        .arg("0.3")
+        .arg("--output-format")
+        .arg("json")
+        .arg("-s")
+        .arg(":::")
+        .arg("echo hi; sleep 2")
+        .assert()
+        .failure()
+        .code(1)
+        .stderr(predicate::str::contains("timeouts=1"));
+
+    let jobs = json_lines(&assert.get_output().stdout);
+
+    assert_eq!(jobs.len(), 1);
+    assert_eq!(jobs[0]["status"], "timeout");
+    assert_eq!(jobs[0]["stdout"], "hi\n");
+    assert_eq!(jobs[0]["stdout_encoding"], "utf8");
+}
+
+#[test]
+fn writes_json_output_format_for_failed_spawns() {
+    let assert = rust_parallel()
+        .arg("--output-format")
+        .arg("json")
+        .arg("command_that_does_not_exist")
+        .arg(":::")
+        .arg("A")
+        .assert()
+        .failure()
+        .code(1)
+        .stdout(predicate::str::contains(
+            r#""command":"command_that_does_not_exist","args":["A"],"status":"spawn_error""#,
+        ))
+        .stderr(predicate::str::contains("error resolving path"));
+
+    let jobs = json_lines(&assert.get_output().stdout);
+
+    assert_eq!(jobs.len(), 1);
+}
+
+#[cfg(unix)]
+#[test]
+fn runs_non_utf8_inputs() {
+    rust_parallel()
+        .arg("-k")
+        .arg("-0")
+        .arg("echo")
+        .arg("{.}")
+        .write_stdin(&b"dir/caf\xe9.txt\0x.txt\0"[..])
+        .assert()
+        .success()
+        .stdout(&b"dir/caf\xe9\nx\n"[..])
+        .stderr(predicate::str::is_empty());
+}
+
+#[cfg(unix)]
+#[test]
+fn runs_non_utf8_args() {
+    use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+    rust_parallel()
+        .arg("-k")
+        .arg("echo")
+        .arg(OsStr::from_bytes(b"\xe9={}"))
+        .arg(":::")
+        .arg(OsStr::from_bytes(b"caf\xe9"))
+        .arg("x")
+        .assert()
+        .success()
+        .stdout(&b"\xe9=caf\xe9\n\xe9=x\n"[..])
+        .stderr(predicate::str::is_empty());
+}
+
+#[cfg(unix)]
+#[test]
+fn runs_non_utf8_colsep_columns() {
+    rust_parallel()
+        .arg("-k")
+        .arg("--colsep")
+        .arg(",")
+        .arg("echo")
+        .arg("{2}-{1}")
+        .write_stdin(&b"caf\xe9,1\nb,2\n"[..])
+        .assert()
+        .success()
+        .stdout(&b"1-caf\xe9\n2-b\n"[..])
+        .stderr(predicate::str::is_empty());
+}
+
+#[test]
+fn warns_about_inputs_that_cant_be_split() {
+    rust_parallel()
+        .arg("-k")
+        .write_stdin("echo a\necho \"b\necho c\n")
+        .assert()
+        .failure()
+        .code(1)
+        .stdout(
+            predicate::str::contains("a\n")
+                .and(predicate::str::contains("c\n"))
+                .and(predicate::str::contains(
+                    "skipping input stdin:2 that can't be parsed: unbalanced quotes or trailing backslash",
+                )),
+        )
+        .stderr(predicate::str::contains("invalid_inputs=1"));
+}